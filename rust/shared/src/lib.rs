@@ -1,35 +1,515 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+/// Version of the wire format, bumped whenever `TalkProtocol` changes incompatibly.
+pub const PROTOCOL_VERSION: u16 = 12;
+/// Oldest protocol version a server still accepts.
+pub const MIN_PROTOCOL_VERSION: u16 = 12;
+
+/// Room every client joins on start, it is always public and nobody owns it.
+pub const DEFAULT_ROOM: &str = "lobby";
+
+/// Optional features agreed on during the `Hello` / `Welcome` handshake.
+pub mod capability {
+    pub const HISTORY: &str = "history";
+    pub const PRESENCE: &str = "presence";
+    pub const RESUME: &str = "resume";
+    pub const DIRECT: &str = "direct";
+    pub const DIRECTORY: &str = "directory";
+    pub const TYPING: &str = "typing";
+    pub const RECEIPTS: &str = "receipts";
+
+    pub const ALL: &[&str] = &[
+        HISTORY, PRESENCE, RESUME, DIRECT, DIRECTORY, TYPING, RECEIPTS,
+    ];
+}
+
+/// Codes sent in `TalkProtocol::Error`.
+pub mod error_code {
+    pub const HANDSHAKE_REQUIRED: &str = "HANDSHAKE_REQUIRED";
+    pub const UNSUPPORTED_VERSION: &str = "UNSUPPORTED_VERSION";
+    pub const UNSUPPORTED_CAPABILITY: &str = "UNSUPPORTED_CAPABILITY";
+    pub const AUTH_REQUIRED: &str = "AUTH_REQUIRED";
+    pub const AUTH_FAILED: &str = "AUTH_FAILED";
+    pub const IDENTITY_MISMATCH: &str = "IDENTITY_MISMATCH";
+    pub const USERNAME_TAKEN: &str = "USERNAME_TAKEN";
+    pub const USERNAME_RESERVED: &str = "USERNAME_RESERVED";
+    pub const LOGIN_FAILED: &str = "LOGIN_FAILED";
+    pub const INVALID_TOKEN: &str = "INVALID_TOKEN";
+    pub const RATE_LIMITED: &str = "RATE_LIMITED";
+    pub const MESSAGE_EMPTY: &str = "MESSAGE_EMPTY";
+    pub const MESSAGE_TOO_LONG: &str = "MESSAGE_TOO_LONG";
+    pub const USERNAME_INVALID: &str = "USERNAME_INVALID";
+    pub const USERNAME_TOO_LONG: &str = "USERNAME_TOO_LONG";
+    pub const PASSWORD_INVALID: &str = "PASSWORD_INVALID";
+    pub const INVALID_FRAME: &str = "INVALID_FRAME";
+    pub const FRAME_TOO_LARGE: &str = "FRAME_TOO_LARGE";
+    pub const USER_NOT_FOUND: &str = "USER_NOT_FOUND";
+    pub const NOT_IN_ROOM: &str = "NOT_IN_ROOM";
+    pub const ROOM_NAME_INVALID: &str = "ROOM_NAME_INVALID";
+    pub const TOPIC_TOO_LONG: &str = "TOPIC_TOO_LONG";
+    pub const ROOM_PASSWORD_REQUIRED: &str = "ROOM_PASSWORD_REQUIRED";
+    pub const ROOM_INVITE_ONLY: &str = "ROOM_INVITE_ONLY";
+    pub const PERMISSION_DENIED: &str = "PERMISSION_DENIED";
+    pub const BANNED: &str = "BANNED";
+    pub const MUTED: &str = "MUTED";
+    pub const MUTE_TOO_LONG: &str = "MUTE_TOO_LONG";
+    pub const MESSAGE_NOT_FOUND: &str = "MESSAGE_NOT_FOUND";
+    pub const REACTION_INVALID: &str = "REACTION_INVALID";
+    pub const TOO_MANY_REACTIONS: &str = "TOO_MANY_REACTIONS";
+}
+
+// Stands in for secrets in `TalkProtocol::redacted`
+const REDACTED: &str = "<redacted>";
+
+/// Bytes a client signs to prove it owns the key bound to `uuid`.
+pub fn auth_payload(challenge: &[u8], uuid: &Uuid) -> Vec<u8> {
+    let mut payload = challenge.to_vec();
+    payload.extend_from_slice(uuid.as_bytes());
+    payload
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct TalkMessage {
     pub uuid: Uuid,
     pub username: String,
     pub text: String,
-    pub room_id: i32,
-    pub unixtime: u64
+    pub room_id: String,
+    pub unixtime: u64,
+    pub message_id: u64,
+    /// Id of the message in the same room this one answers.
+    pub reply_to: Option<u64>,
+    pub edited: bool,
+    /// A deleted message stays in the history as a tombstone without its text.
+    pub deleted: bool,
+    pub reactions: Vec<Reaction>,
+    /// Members of the room the message mentions, resolved by the server.
+    pub mentions: Vec<Uuid>,
+}
+
+impl TalkMessage {
+    /// Adds the user's reaction, false if they already reacted with the emoji.
+    pub fn react(&mut self, emoji: &str, uuid: Uuid) -> bool {
+        match self
+            .reactions
+            .iter_mut()
+            .find(|reaction| reaction.emoji == emoji)
+        {
+            Some(reaction) if reaction.users.contains(&uuid) => false,
+            Some(reaction) => {
+                reaction.users.push(uuid);
+                true
+            }
+            None => {
+                self.reactions.push(Reaction {
+                    emoji: emoji.to_string(),
+                    users: vec![uuid],
+                });
+                true
+            }
+        }
+    }
+
+    /// Whether the message mentions the user, by uuid or by the name they go by now.
+    pub fn mentions_user(&self, uuid: Uuid, username: &str) -> bool {
+        self.mentions.contains(&uuid)
+            || mention::mentioned_names(&self.text)
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(username))
+    }
+
+    /// Removes the user's reaction, an emoji nobody reacts with anymore is dropped.
+    pub fn unreact(&mut self, emoji: &str, uuid: Uuid) -> bool {
+        let Some(reaction) = self
+            .reactions
+            .iter_mut()
+            .find(|reaction| reaction.emoji == emoji)
+        else {
+            return false;
+        };
+        let before = reaction.users.len();
+        reaction.users.retain(|user| *user != uuid);
+        let removed = reaction.users.len() < before;
+        self.reactions.retain(|reaction| !reaction.users.is_empty());
+        removed
+    }
+}
+
+/// Everyone who reacted to a message with the same emoji, in the order they reacted.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Reaction {
+    pub emoji: String,
+    pub users: Vec<Uuid>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RoomMember {
+    pub uuid: Uuid,
+    pub username: String,
+}
+
+/// How far a member of a room has read, `message_id` is the newest message they saw.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReadReceipt {
+    pub uuid: Uuid,
+    pub username: String,
+    pub message_id: u64,
+}
+
+/// Who may join a room.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub enum RoomAccess {
+    #[default]
+    Public,
+    Password,
+    InviteOnly,
+}
+
+/// Standing of a user in a room, ordered from least to most privileged.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum RoomRole {
+    #[default]
+    Member,
+    Moderator,
+    Owner,
+}
+
+/// What a moderator did to a member of a room.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum ModerationAction {
+    Kicked,
+    Banned,
+    Unbanned,
+    // until is a unix timestamp in milliseconds
+    Muted { until: u64 },
+    RoleChanged { role: RoomRole },
+}
+
+/// What the server keeps about a room, `room_id` is the lowercase form of `display_name`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RoomMetadata {
+    pub room_id: String,
+    pub display_name: String,
+    pub topic: Option<String>,
+    pub created: u64,
+    pub creator: String,
+    pub access: RoomAccess,
+}
+
+/// A room as listed in the room directory.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RoomSummary {
+    pub room_id: String,
+    pub display_name: String,
+    pub topic: Option<String>,
+    pub members: usize,
+    pub last_activity: u64,
+    pub access: RoomAccess,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum TalkProtocol {
     // Client -> Server Commands
-    JoinRoom { room_id: i32, uuid: Uuid, username: String, unixtime: u64},
-    LeaveRoom { room_id: i32, uuid: Uuid, username: String, unixtime: u64},
-    ChangeName { room_id: i32, uuid: Uuid, username: String, old_username: String, unixtime: u64},
-    Fetch { room_id: i32, limit: i64, fetch_before: u64},
-    LocalError { message: String },
-    LocalInformation { message: String },
+    JoinRoom {
+        room_id: String,
+        uuid: Uuid,
+        username: String,
+        unixtime: u64,
+        password: Option<String>,
+    },
+    LeaveRoom {
+        room_id: String,
+        uuid: Uuid,
+        username: String,
+        unixtime: u64,
+    },
+    ChangeName {
+        room_id: String,
+        uuid: Uuid,
+        username: String,
+        old_username: String,
+        unixtime: u64,
+    },
+    // fetch_before is a message id, see TalkProtocol::message_id
+    Fetch {
+        room_id: String,
+        limit: i64,
+        fetch_before: u64,
+    },
+    LocalError {
+        message: String,
+    },
+    LocalInformation {
+        message: String,
+    },
 
     // Server -> Client Events
-    UserJoined { uuid: Uuid, username: String, room_id: i32, unixtime: u64 },
-    UserLeft { uuid: Uuid, username: String, room_id: i32, unixtime: u64  },
-    UsernameChanged {uuid: Uuid, username: String, old_username: String, unixtime: u64},
-    History { text: Vec<TalkProtocol> },
-    Error { code: String, message: String },
-
+    UserJoined {
+        uuid: Uuid,
+        username: String,
+        room_id: String,
+        unixtime: u64,
+        message_id: u64,
+    },
+    UserLeft {
+        uuid: Uuid,
+        username: String,
+        room_id: String,
+        unixtime: u64,
+        reason: Option<String>,
+        message_id: u64,
+    },
+    UsernameChanged {
+        uuid: Uuid,
+        username: String,
+        old_username: String,
+        room_id: String,
+        unixtime: u64,
+        message_id: u64,
+    },
+    History {
+        room_id: String,
+        text: Vec<TalkProtocol>,
+    },
+    Error {
+        code: String,
+        message: String,
+    },
 
     // Server <-> Client
     PostMessage { message: TalkMessage },
+
+    // Client only
+    LocalDivider {
+        label: String,
+    },
+
+    // Client -> Server Presence
+    Who {
+        room_id: String,
+    },
+
+    // Server -> Client Presence
+    Members {
+        room_id: String,
+        members: Vec<RoomMember>,
+    },
+
+    // Client -> Server Resume, fetch_after is a message id
+    FetchAfter {
+        room_id: String,
+        limit: i64,
+        fetch_after: u64,
+    },
+
+    // Handshake, always the first frames on a connection
+    Hello {
+        protocol_version: u16,
+        client_name: String,
+        capabilities: Vec<String>,
+    },
+    Welcome {
+        server_version: String,
+        capabilities: Vec<String>,
+        motd: Option<String>,
+        challenge: Vec<u8>,
+    },
+    Authenticate {
+        uuid: Uuid,
+        public_key: Vec<u8>,
+        signature: Vec<u8>,
+    },
+    Authenticated {
+        uuid: Uuid,
+    },
+
+    // Client -> Server Accounts
+    Register {
+        username: String,
+        password: String,
+    },
+    Login {
+        username: String,
+        password: String,
+    },
+    TokenLogin {
+        token: String,
+    },
+
+    // Server -> Client Accounts
+    LoggedIn {
+        username: String,
+        uuid: Uuid,
+        token: String,
+    },
+
+    // Server <-> Client Direct messages, recipient_uuid is filled in by the server
+    // and message_id counts per conversation instead of per room
+    DirectMessage {
+        uuid: Uuid,
+        username: String,
+        recipient_uuid: Uuid,
+        recipient: String,
+        text: String,
+        unixtime: u64,
+        message_id: u64,
+    },
+
+    // Client -> Server Direct messages
+    FetchConversation {
+        username: String,
+        limit: i64,
+    },
+
+    // Server -> Client Direct messages
+    Conversation {
+        username: String,
+        messages: Vec<TalkProtocol>,
+    },
+
+    // Client -> Server Rooms, an empty topic removes it
+    SetTopic {
+        room_id: String,
+        uuid: Uuid,
+        topic: String,
+    },
+
+    // Server -> Client Rooms
+    RoomInfo {
+        room: RoomMetadata,
+    },
+    TopicChanged {
+        uuid: Uuid,
+        username: String,
+        room_id: String,
+        topic: Option<String>,
+        unixtime: u64,
+        message_id: u64,
+    },
+
+    // Client -> Server Room directory
+    ListRooms,
+
+    // Server -> Client Room directory, most recently active first
+    RoomList {
+        rooms: Vec<RoomSummary>,
+    },
+
+    // Client -> Server Room access, password is only used with RoomAccess::Password
+    SetRoomAccess {
+        room_id: String,
+        access: RoomAccess,
+        password: Option<String>,
+    },
+    Invite {
+        room_id: String,
+        username: String,
+    },
+
+    // Server -> Client Room access
+    RoomAccessChanged {
+        uuid: Uuid,
+        username: String,
+        room_id: String,
+        access: RoomAccess,
+        unixtime: u64,
+        message_id: u64,
+    },
+    Invited {
+        room_id: String,
+        display_name: String,
+        username: String,
+        invitee: String,
+    },
+
+    // Client -> Server Moderation, the server checks the sender's role in the room
+    Kick {
+        room_id: String,
+        username: String,
+        reason: Option<String>,
+    },
+    Ban {
+        room_id: String,
+        username: String,
+        reason: Option<String>,
+    },
+    Unban {
+        room_id: String,
+        username: String,
+    },
+    // duration is in seconds
+    Mute {
+        room_id: String,
+        username: String,
+        duration: u64,
+    },
+    SetRole {
+        room_id: String,
+        username: String,
+        role: RoomRole,
+    },
+
+    // Server -> Client Moderation, uuid and username are the moderator's
+    Moderated {
+        uuid: Uuid,
+        username: String,
+        room_id: String,
+        target_uuid: Uuid,
+        target: String,
+        action: ModerationAction,
+        reason: Option<String>,
+        unixtime: u64,
+        message_id: u64,
+    },
+
+    // Server <-> Client Changes to a posted message, uuid is whoever changed it
+    EditMessage {
+        room_id: String,
+        message_id: u64,
+        uuid: Uuid,
+        text: String,
+    },
+    DeleteMessage {
+        room_id: String,
+        message_id: u64,
+        uuid: Uuid,
+    },
+
+    // Server <-> Client Reactions, uuid is whoever reacted
+    React {
+        room_id: String,
+        message_id: u64,
+        uuid: Uuid,
+        emoji: String,
+    },
+    Unreact {
+        room_id: String,
+        message_id: u64,
+        uuid: Uuid,
+        emoji: String,
+    },
+
+    // Server <-> Client Typing indicator, relayed to the room but never stored
+    Typing {
+        room_id: String,
+        uuid: Uuid,
+        username: String,
+    },
+
+    // Server <-> Client Read receipts, relayed to the room and kept per user
+    MarkRead {
+        room_id: String,
+        uuid: Uuid,
+        username: String,
+        message_id: u64,
+    },
+
+    // Server -> Client Read receipts of a room, sent on join before the history
+    ReadState {
+        room_id: String,
+        last_read: Option<u64>,
+        receipts: Vec<ReadReceipt>,
+    },
 }
 
 impl TalkProtocol {
@@ -40,6 +520,170 @@ impl TalkProtocol {
     pub fn deserialize(bytes: &[u8]) -> Result<Self, bincode::Error> {
         bincode::deserialize(bytes)
     }
+
+    /// Timestamp of a room event or direct message in milliseconds, `None` for requests and local-only messages.
+    pub fn unixtime(&self) -> Option<u64> {
+        match self {
+            TalkProtocol::PostMessage { message } => Some(message.unixtime),
+            TalkProtocol::DirectMessage { unixtime, .. } => Some(*unixtime),
+            TalkProtocol::TopicChanged { unixtime, .. } => Some(*unixtime),
+            TalkProtocol::RoomAccessChanged { unixtime, .. } => Some(*unixtime),
+            TalkProtocol::Moderated { unixtime, .. } => Some(*unixtime),
+            TalkProtocol::UserJoined { unixtime, .. } => Some(*unixtime),
+            TalkProtocol::UserLeft { unixtime, .. } => Some(*unixtime),
+            TalkProtocol::UsernameChanged { unixtime, .. } => Some(*unixtime),
+            _ => None,
+        }
+    }
+
+    /// Per-room id the server assigned to a room event, `None` for everything else.
+    pub fn message_id(&self) -> Option<u64> {
+        match self {
+            TalkProtocol::PostMessage { message } => Some(message.message_id),
+            TalkProtocol::UserJoined { message_id, .. } => Some(*message_id),
+            TalkProtocol::UserLeft { message_id, .. } => Some(*message_id),
+            TalkProtocol::UsernameChanged { message_id, .. } => Some(*message_id),
+            TalkProtocol::TopicChanged { message_id, .. } => Some(*message_id),
+            TalkProtocol::RoomAccessChanged { message_id, .. } => Some(*message_id),
+            TalkProtocol::Moderated { message_id, .. } => Some(*message_id),
+            _ => None,
+        }
+    }
+
+    /// Room a frame belongs to, `None` for frames that are not tied to a room.
+    pub fn room_id(&self) -> Option<&str> {
+        match self {
+            TalkProtocol::PostMessage { message } => Some(&message.room_id),
+            TalkProtocol::UserJoined { room_id, .. }
+            | TalkProtocol::UserLeft { room_id, .. }
+            | TalkProtocol::UsernameChanged { room_id, .. }
+            | TalkProtocol::History { room_id, .. }
+            | TalkProtocol::Members { room_id, .. }
+            | TalkProtocol::RoomInfo {
+                room: RoomMetadata { room_id, .. },
+            }
+            | TalkProtocol::TopicChanged { room_id, .. }
+            | TalkProtocol::RoomAccessChanged { room_id, .. }
+            | TalkProtocol::Moderated { room_id, .. }
+            | TalkProtocol::EditMessage { room_id, .. }
+            | TalkProtocol::DeleteMessage { room_id, .. }
+            | TalkProtocol::React { room_id, .. }
+            | TalkProtocol::Unreact { room_id, .. }
+            | TalkProtocol::Typing { room_id, .. }
+            | TalkProtocol::MarkRead { room_id, .. }
+            | TalkProtocol::ReadState { room_id, .. } => Some(room_id),
+            _ => None,
+        }
+    }
+
+    /// Identity a client frame claims to come from.
+    pub fn sender_uuid(&self) -> Option<Uuid> {
+        match self {
+            TalkProtocol::JoinRoom { uuid, .. } => Some(*uuid),
+            TalkProtocol::LeaveRoom { uuid, .. } => Some(*uuid),
+            TalkProtocol::ChangeName { uuid, .. } => Some(*uuid),
+            TalkProtocol::PostMessage { message } => Some(message.uuid),
+            TalkProtocol::DirectMessage { uuid, .. } => Some(*uuid),
+            TalkProtocol::SetTopic { uuid, .. } => Some(*uuid),
+            TalkProtocol::EditMessage { uuid, .. } => Some(*uuid),
+            TalkProtocol::DeleteMessage { uuid, .. } => Some(*uuid),
+            TalkProtocol::React { uuid, .. } => Some(*uuid),
+            TalkProtocol::Unreact { uuid, .. } => Some(*uuid),
+            TalkProtocol::Typing { uuid, .. } => Some(*uuid),
+            TalkProtocol::MarkRead { uuid, .. } => Some(*uuid),
+            _ => None,
+        }
+    }
+
+    /// Replaces the identity of a client frame, used by the server for logged in sessions.
+    pub fn set_sender_uuid(&mut self, sender: Uuid) {
+        match self {
+            TalkProtocol::JoinRoom { uuid, .. }
+            | TalkProtocol::LeaveRoom { uuid, .. }
+            | TalkProtocol::ChangeName { uuid, .. }
+            | TalkProtocol::DirectMessage { uuid, .. }
+            | TalkProtocol::SetTopic { uuid, .. }
+            | TalkProtocol::EditMessage { uuid, .. }
+            | TalkProtocol::DeleteMessage { uuid, .. }
+            | TalkProtocol::React { uuid, .. }
+            | TalkProtocol::Unreact { uuid, .. }
+            | TalkProtocol::Typing { uuid, .. }
+            | TalkProtocol::MarkRead { uuid, .. } => *uuid = sender,
+            TalkProtocol::PostMessage { message } => message.uuid = sender,
+            _ => {}
+        }
+    }
+
+    /// Copy of the frame without passwords and session tokens, for logging.
+    pub fn redacted(&self) -> TalkProtocol {
+        let mut redacted = self.clone();
+        match &mut redacted {
+            TalkProtocol::Register { password, .. } | TalkProtocol::Login { password, .. } => {
+                *password = REDACTED.to_string()
+            }
+            TalkProtocol::JoinRoom { password, .. }
+            | TalkProtocol::SetRoomAccess { password, .. } => {
+                if let Some(password) = password {
+                    *password = REDACTED.to_string()
+                }
+            }
+            TalkProtocol::TokenLogin { token } | TalkProtocol::LoggedIn { token, .. } => {
+                *token = REDACTED.to_string()
+            }
+            _ => {}
+        }
+        redacted
+    }
+
+    /// Overwrites id and timestamp of a room event or direct message, used by the server before relaying it.
+    pub fn stamp(&mut self, id: u64, time: u64) {
+        match self {
+            TalkProtocol::PostMessage { message } => {
+                message.message_id = id;
+                message.unixtime = time;
+            }
+            TalkProtocol::UserJoined {
+                message_id,
+                unixtime,
+                ..
+            }
+            | TalkProtocol::UserLeft {
+                message_id,
+                unixtime,
+                ..
+            }
+            | TalkProtocol::UsernameChanged {
+                message_id,
+                unixtime,
+                ..
+            }
+            | TalkProtocol::DirectMessage {
+                message_id,
+                unixtime,
+                ..
+            }
+            | TalkProtocol::TopicChanged {
+                message_id,
+                unixtime,
+                ..
+            }
+            | TalkProtocol::RoomAccessChanged {
+                message_id,
+                unixtime,
+                ..
+            }
+            | TalkProtocol::Moderated {
+                message_id,
+                unixtime,
+                ..
+            } => {
+                *message_id = id;
+                *unixtime = time;
+            }
+            _ => {}
+        }
+    }
+
     pub fn to_i16(&self) -> Option<i16> {
         match self {
             TalkProtocol::UserJoined {..} => Some(0),
@@ -51,18 +695,380 @@ impl TalkProtocol {
         }
     }
 
-    pub fn from_i16(value: i16, room_id: i32, uuid: Uuid, username: String, unixtime: u64, message: String) -> Option<Self> {
+    pub fn from_i16(
+        value: i16,
+        room_id: String,
+        uuid: Uuid,
+        username: String,
+        unixtime: u64,
+        message: String,
+    ) -> Option<Self> {
         Some(match value {
-            0 => TalkProtocol::UserJoined { uuid, username, room_id, unixtime },
-            1 => TalkProtocol::UserLeft { uuid, username, room_id, unixtime },
-            2 => TalkProtocol::UsernameChanged { uuid, username, old_username: message, unixtime },
-            3 => TalkProtocol::Error { code: message.clone(), message },
-            4 => TalkProtocol::PostMessage { message: TalkMessage { uuid, username, text: message, room_id, unixtime } },
+            0 => TalkProtocol::UserJoined {
+                uuid,
+                username,
+                room_id,
+                unixtime,
+                message_id: 0,
+            },
+            1 => TalkProtocol::UserLeft {
+                uuid,
+                username,
+                room_id,
+                unixtime,
+                reason: None,
+                message_id: 0,
+            },
+            2 => TalkProtocol::UsernameChanged {
+                uuid,
+                username,
+                old_username: message,
+                room_id,
+                unixtime,
+                message_id: 0,
+            },
+            3 => TalkProtocol::Error {
+                code: message.clone(),
+                message,
+            },
+            4 => TalkProtocol::PostMessage {
+                message: TalkMessage {
+                    uuid,
+                    username,
+                    text: message,
+                    room_id,
+                    unixtime,
+                    message_id: 0,
+                    reply_to: None,
+                    edited: false,
+                    deleted: false,
+                    reactions: Vec::new(),
+                    mentions: Vec::new(),
+                },
+            },
             _ => return None,
         })
     }
 }
 
+/// Limits both sides agree on, lengths are counted in grapheme clusters
+/// so an emoji or an accented letter counts as a single character.
+pub mod validation {
+    use super::error_code;
+    use std::fmt;
+    use unicode_segmentation::UnicodeSegmentation;
+
+    pub const MESSAGE_LENGTH: usize = 250;
+    pub const USERNAME_LENGTH: usize = 15;
+    pub const ROOM_NAME_LENGTH: usize = 32;
+    pub const TOPIC_LENGTH: usize = 120;
+    pub const PASSWORD_MIN_LENGTH: usize = 8;
+    pub const PASSWORD_LENGTH: usize = 128;
+    /// Different emojis a single message can collect.
+    pub const MAX_REACTIONS: usize = 20;
+    /// Largest WebSocket message or frame the server reads from a client.
+    pub const MAX_FRAME_SIZE: usize = 16 * 1024;
+    /// Longest mute a moderator can hand out, in seconds.
+    pub const MAX_MUTE_SECONDS: u64 = 365 * 24 * 60 * 60;
+
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum ValidationError {
+        MessageEmpty,
+        MessageTooLong,
+        UsernameInvalid,
+        UsernameTooLong,
+        PasswordInvalid,
+        RoomNameInvalid,
+        TopicTooLong,
+        ReactionInvalid,
+    }
+
+    impl ValidationError {
+        /// The `error_code` the server answers with.
+        pub fn code(&self) -> &'static str {
+            match self {
+                ValidationError::MessageEmpty => error_code::MESSAGE_EMPTY,
+                ValidationError::MessageTooLong => error_code::MESSAGE_TOO_LONG,
+                ValidationError::UsernameInvalid => error_code::USERNAME_INVALID,
+                ValidationError::UsernameTooLong => error_code::USERNAME_TOO_LONG,
+                ValidationError::PasswordInvalid => error_code::PASSWORD_INVALID,
+                ValidationError::RoomNameInvalid => error_code::ROOM_NAME_INVALID,
+                ValidationError::TopicTooLong => error_code::TOPIC_TOO_LONG,
+                ValidationError::ReactionInvalid => error_code::REACTION_INVALID,
+            }
+        }
+    }
+
+    impl fmt::Display for ValidationError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                ValidationError::MessageEmpty => write!(f, "Message is empty"),
+                ValidationError::MessageTooLong => {
+                    write!(f, "Message is longer than {} characters", MESSAGE_LENGTH)
+                }
+                ValidationError::UsernameInvalid => {
+                    write!(f, "Username must not be empty or contain whitespace")
+                }
+                ValidationError::UsernameTooLong => {
+                    write!(f, "Username is longer than {} characters", USERNAME_LENGTH)
+                }
+                ValidationError::PasswordInvalid => write!(
+                    f,
+                    "Passwords have {} to {} characters",
+                    PASSWORD_MIN_LENGTH, PASSWORD_LENGTH
+                ),
+                ValidationError::RoomNameInvalid => write!(
+                    f,
+                    "Room names have 1 to {} letters, digits, '-' or '_'",
+                    ROOM_NAME_LENGTH
+                ),
+                ValidationError::TopicTooLong => {
+                    write!(f, "Topic is longer than {} characters", TOPIC_LENGTH)
+                }
+                ValidationError::ReactionInvalid => {
+                    write!(f, "A reaction is a single emoji or symbol")
+                }
+            }
+        }
+    }
+
+    impl std::error::Error for ValidationError {}
+
+    pub fn grapheme_count(text: &str) -> usize {
+        text.graphemes(true).count()
+    }
+
+    pub fn validate_message(text: &str) -> Result<(), ValidationError> {
+        if text.trim().is_empty() {
+            return Err(ValidationError::MessageEmpty);
+        }
+        if grapheme_count(text) > MESSAGE_LENGTH {
+            return Err(ValidationError::MessageTooLong);
+        }
+        Ok(())
+    }
+
+    pub fn validate_username(username: &str) -> Result<(), ValidationError> {
+        if username.is_empty()
+            || username
+                .chars()
+                .any(|c| c.is_whitespace() || c.is_control())
+        {
+            return Err(ValidationError::UsernameInvalid);
+        }
+        if grapheme_count(username) > USERNAME_LENGTH {
+            return Err(ValidationError::UsernameTooLong);
+        }
+        Ok(())
+    }
+
+    pub fn validate_password(password: &str) -> Result<(), ValidationError> {
+        if !(PASSWORD_MIN_LENGTH..=PASSWORD_LENGTH).contains(&grapheme_count(password)) {
+            return Err(ValidationError::PasswordInvalid);
+        }
+        Ok(())
+    }
+
+    pub fn validate_room_name(name: &str) -> Result<(), ValidationError> {
+        if name.is_empty()
+            || name.len() > ROOM_NAME_LENGTH
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(ValidationError::RoomNameInvalid);
+        }
+        Ok(())
+    }
+
+    /// Id of the room called `name`, names differing only in case are the same room.
+    pub fn room_key(name: &str) -> String {
+        name.to_ascii_lowercase()
+    }
+
+    pub fn validate_topic(topic: &str) -> Result<(), ValidationError> {
+        if grapheme_count(topic) > TOPIC_LENGTH {
+            return Err(ValidationError::TopicTooLong);
+        }
+        Ok(())
+    }
+
+    /// Reactions are a single grapheme, letters and digits are not reactions.
+    pub fn validate_reaction(emoji: &str) -> Result<(), ValidationError> {
+        if grapheme_count(emoji) != 1
+            || emoji
+                .chars()
+                .any(|c| c.is_alphanumeric() || c.is_whitespace() || c.is_control())
+        {
+            return Err(ValidationError::ReactionInvalid);
+        }
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn messages_are_limited_by_graphemes() {
+            assert_eq!(validate_message("  "), Err(ValidationError::MessageEmpty));
+            assert_eq!(validate_message(&"a".repeat(MESSAGE_LENGTH)), Ok(()));
+            assert_eq!(
+                validate_message(&"a".repeat(MESSAGE_LENGTH + 1)),
+                Err(ValidationError::MessageTooLong)
+            );
+            // a family emoji is several chars but a single grapheme
+            let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+            assert_eq!(validate_message(&family.repeat(MESSAGE_LENGTH)), Ok(()));
+        }
+
+        #[test]
+        fn usernames_have_no_whitespace_and_a_length_limit() {
+            assert_eq!(validate_username("alice"), Ok(()));
+            assert_eq!(validate_username(""), Err(ValidationError::UsernameInvalid));
+            assert_eq!(
+                validate_username("al ice"),
+                Err(ValidationError::UsernameInvalid)
+            );
+            assert_eq!(
+                validate_username("al\u{7}ice"),
+                Err(ValidationError::UsernameInvalid)
+            );
+            assert_eq!(validate_username(&"ä".repeat(USERNAME_LENGTH)), Ok(()));
+            assert_eq!(
+                validate_username(&"a".repeat(USERNAME_LENGTH + 1)),
+                Err(ValidationError::UsernameTooLong)
+            );
+        }
+
+        #[test]
+        fn passwords_have_a_minimum_and_a_maximum_length() {
+            assert_eq!(validate_password(&"a".repeat(PASSWORD_MIN_LENGTH)), Ok(()));
+            assert_eq!(validate_password(&"a".repeat(PASSWORD_LENGTH)), Ok(()));
+            for password in [
+                "",
+                &"a".repeat(PASSWORD_MIN_LENGTH - 1),
+                &"a".repeat(PASSWORD_LENGTH + 1),
+            ] {
+                assert_eq!(
+                    validate_password(password),
+                    Err(ValidationError::PasswordInvalid)
+                );
+            }
+        }
+
+        #[test]
+        fn room_names_are_ascii_words() {
+            assert_eq!(validate_room_name("rust-lang_2"), Ok(()));
+            assert_eq!(validate_room_name(&"a".repeat(ROOM_NAME_LENGTH)), Ok(()));
+            for name in [
+                "",
+                "with space",
+                "café",
+                "#rust",
+                &"a".repeat(ROOM_NAME_LENGTH + 1),
+            ] {
+                assert_eq!(
+                    validate_room_name(name),
+                    Err(ValidationError::RoomNameInvalid)
+                );
+            }
+            assert_eq!(room_key("Rust"), room_key("rUST"));
+        }
+
+        #[test]
+        fn reactions_are_a_single_symbol() {
+            for emoji in [
+                "👍",
+                "❤️",
+                "🇩🇪",
+                "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}",
+                "+",
+            ] {
+                assert_eq!(validate_reaction(emoji), Ok(()), "{}", emoji);
+            }
+            for emoji in ["", "a", "7", " ", "\n", "👍👍", "👍 "] {
+                assert_eq!(
+                    validate_reaction(emoji),
+                    Err(ValidationError::ReactionInvalid),
+                    "{:?}",
+                    emoji
+                );
+            }
+        }
+
+        #[test]
+        fn topics_may_be_empty_but_not_too_long() {
+            assert_eq!(validate_topic(""), Ok(()));
+            assert_eq!(validate_topic(&"a".repeat(TOPIC_LENGTH)), Ok(()));
+            assert_eq!(
+                validate_topic(&"a".repeat(TOPIC_LENGTH + 1)),
+                Err(ValidationError::TopicTooLong)
+            );
+        }
+    }
+}
+
+/// `@name` mentions in message texts, names compare like usernames and ignore case.
+pub mod mention {
+    use super::RoomMember;
+    use uuid::Uuid;
+
+    // Punctuation that ends a sentence rather than belongs to the name, as in "thanks @bob!"
+    const TRAILING: &[char] = &['.', ',', '!', '?', ':', ';', ')', '"', '\''];
+
+    /// Names written after an `@`, in the order they appear.
+    pub fn mentioned_names(text: &str) -> Vec<&str> {
+        text.split_whitespace()
+            .filter_map(|word| word.strip_prefix('@'))
+            .map(|name| name.trim_end_matches(TRAILING))
+            .filter(|name| !name.is_empty())
+            .collect()
+    }
+
+    /// The members of a room the text mentions, each one once.
+    pub fn resolve_mentions(text: &str, members: &[RoomMember]) -> Vec<Uuid> {
+        let mut mentioned = Vec::new();
+        for name in mentioned_names(text) {
+            if let Some(member) = members
+                .iter()
+                .find(|member| member.username.eq_ignore_ascii_case(name))
+                && !mentioned.contains(&member.uuid)
+            {
+                mentioned.push(member.uuid);
+            }
+        }
+        mentioned
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn member(username: &str) -> RoomMember {
+            RoomMember {
+                uuid: Uuid::new_v4(),
+                username: username.to_string(),
+            }
+        }
+
+        #[test]
+        fn names_end_before_trailing_punctuation() {
+            assert_eq!(
+                mentioned_names("thanks @bob! and @Alice, see mail@example.com @"),
+                ["bob", "Alice"]
+            );
+        }
+
+        #[test]
+        fn mentions_resolve_to_members_once() {
+            let members = [member("alice"), member("bob")];
+            let mentioned = resolve_mentions("@ALICE @carol @alice @bob?", &members);
+            assert_eq!(mentioned, [members[0].uuid, members[1].uuid]);
+        }
+    }
+}
+
 pub mod native {
     use super::*;
     use futures_channel::mpsc::UnboundedReceiver;
@@ -70,6 +1076,8 @@ pub mod native {
         SinkExt, StreamExt,
         stream::{SplitSink, SplitStream},
     };
+    use std::collections::VecDeque;
+    use std::time::{Duration, Instant};
     use tokio::net::TcpStream;
     use tokio_tungstenite::tungstenite::Error as WsError;
     use tokio_tungstenite::{
@@ -78,6 +1086,46 @@ pub mod native {
     };
     type WebStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
+    // Stays below the server's default bucket of 10 frames refilled at 2 per second,
+    // frames over the limit would be dropped by the server after they left the queue
+    const SEND_BURST: f64 = 8.0;
+    const SEND_PER_SECOND: f64 = 2.0;
+
+    /// Token bucket for outgoing frames. It outlives single connections like
+    /// the server's bucket for the uuid does, so a reconnect can't flush too much at once.
+    pub struct SendPacer {
+        tokens: f64,
+        last_refill: Instant,
+    }
+
+    impl Default for SendPacer {
+        fn default() -> Self {
+            Self {
+                tokens: SEND_BURST,
+                last_refill: Instant::now(),
+            }
+        }
+    }
+
+    impl SendPacer {
+        fn refill(&mut self) {
+            let elapsed = self.last_refill.elapsed().as_secs_f64();
+            self.last_refill = Instant::now();
+            self.tokens = (self.tokens + elapsed * SEND_PER_SECOND).min(SEND_BURST);
+        }
+
+        /// Waits until the next frame can be sent.
+        pub async fn take(&mut self) {
+            self.refill();
+            if self.tokens < 1.0 {
+                let wait = (1.0 - self.tokens) / SEND_PER_SECOND;
+                tokio::time::sleep(Duration::from_secs_f64(wait)).await;
+                self.refill();
+            }
+            self.tokens -= 1.0;
+        }
+    }
+
     pub async fn connect(
         url: String,
     ) -> Result<(SplitSink<WebStream, Message>, SplitStream<WebStream>), Error> {
@@ -85,30 +1133,96 @@ pub mod native {
         Ok(stream.split())
     }
 
+    /// Sends `hello`, answers the challenge of the `Welcome` with `authenticate` and
+    /// waits until the server accepted it. Returns the `Welcome`, or the `Error` that
+    /// ended the handshake.
+    pub async fn handshake(
+        write: &mut SplitSink<WebStream, Message>,
+        read: &mut SplitStream<WebStream>,
+        hello: TalkProtocol,
+        authenticate: impl FnOnce(&[u8]) -> TalkProtocol,
+    ) -> Result<TalkProtocol, WsError> {
+        let mut authenticate = Some(authenticate);
+        let mut welcome = None;
+        write
+            .send(Message::Binary(serialize_frame(&hello)?))
+            .await?;
+
+        while let Some(msg) = read.next().await {
+            let Message::Binary(bin) = msg? else {
+                continue;
+            };
+            match TalkProtocol::deserialize(&bin) {
+                Ok(frame @ TalkProtocol::Welcome { .. }) => {
+                    let (Some(authenticate), TalkProtocol::Welcome { challenge, .. }) =
+                        (authenticate.take(), &frame)
+                    else {
+                        continue;
+                    };
+                    let answer = authenticate(challenge);
+                    write
+                        .send(Message::Binary(serialize_frame(&answer)?))
+                        .await?;
+                    welcome = Some(frame);
+                }
+                Ok(TalkProtocol::Authenticated { .. }) => {
+                    if let Some(welcome) = welcome {
+                        return Ok(welcome);
+                    }
+                }
+                Ok(error @ TalkProtocol::Error { .. }) => return Ok(error),
+                _ => {}
+            }
+        }
+        Err(WsError::ConnectionClosed)
+    }
+
+    fn serialize_frame(msg: &TalkProtocol) -> std::io::Result<Vec<u8>> {
+        msg.serialize().map_err(std::io::Error::other)
+    }
+
+    /// Writes `pending` and then everything arriving on `rx` until the socket fails,
+    /// paced by `pacer`. A message only leaves `pending` once it has been written,
+    /// so a dropped connection never loses what was queued.
     pub async fn sender_task(
-        mut rx: UnboundedReceiver<TalkProtocol>,
+        rx: &mut UnboundedReceiver<TalkProtocol>,
+        pending: &mut VecDeque<TalkProtocol>,
+        pacer: &mut SendPacer,
         mut write: SplitSink<WebStream, Message>,
+        mut on_sent: impl FnMut(&TalkProtocol),
     ) {
-        while let Some(msg) = rx.next().await {
-            match bincode::serialize(&msg) {
+        loop {
+            if pending.is_empty() {
+                match rx.next().await {
+                    Some(msg) => pending.push_back(msg),
+                    None => break,
+                }
+            }
+            let Some(msg) = pending.front() else {
+                continue;
+            };
+            match bincode::serialize(msg) {
                 Ok(bin) => {
+                    pacer.take().await;
                     if let Err(e) = write.send(Message::Binary(bin)).await {
-                        eprintln!("WebSocket send error: {:?}", e);
+                        log::warn!("WebSocket send error: {:?}", e);
                         break;
                     }
+                    on_sent(msg);
                 }
                 Err(e) => {
-                    eprintln!("Serialization error: {:?}", e);
+                    log::error!("Serialization error: {:?}", e);
                 }
             }
+            pending.pop_front();
         }
 
-        println!("Sender task ended");
+        log::info!("Sender task ended");
     }
 
     pub async fn receiver_task(
         mut read: SplitStream<WebStream>,
-        mut on_message: impl FnMut(TalkProtocol) + Send + 'static,
+        mut on_message: impl FnMut(TalkProtocol) + Send,
     ) -> Result<(), WsError> {
         while let Some(msg) = read.next().await {
             match msg {
@@ -119,7 +1233,7 @@ pub mod native {
                 }
                 Ok(Message::Text(text)) => {
                     // Optional: Handle text messages if you expect them
-                    println!("Received text message: {}", text);
+                    log::info!("Received text message: {}", text);
                 }
                 Ok(_) => {} // Ignore other message types
                 Err(e) => return Err(e),
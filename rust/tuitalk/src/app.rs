@@ -1,17 +1,27 @@
 use crate::command;
+use crate::completion::Completion;
+use crate::connection::ConnectionState;
+use crate::identity::Identity;
+use crate::rooms::{RoomDirectory, Rooms};
 use crate::ui;
 use color_eyre::Result;
-use futures_channel::mpsc::UnboundedSender;
+use futures_channel::mpsc::{UnboundedReceiver, UnboundedSender};
 use ratatui::DefaultTerminal;
 use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::crossterm::{execute, style::Print, terminal::SetTitle};
 use tuitalk_shared::*;
-use std::fs;
+use std::io;
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use uuid::Uuid;
 
 const FAST_SCROLL: usize = 10;
 const DEFAULT_SCROLL: usize = 1;
+// Typing is announced at most this often while the input changes
+const TYPING_INTERVAL: Duration = Duration::from_secs(3);
+// Read positions are sent at most this often
+const READ_INTERVAL: Duration = Duration::from_secs(2);
+const TITLE: &str = "TuiTalk";
 
 pub struct App {
     pub input: String,
@@ -20,11 +30,35 @@ pub struct App {
     pub scroll: usize,
     pub max_scroll: usize,
     pub auto_scroll: bool,
-    pub communication: Arc<Mutex<Vec<TalkProtocol>>>,
+    pub communication: Arc<Mutex<Rooms>>,
     pub tx: UnboundedSender<TalkProtocol>,
+    pub incoming: UnboundedReceiver<TalkProtocol>,
+    pub connection: Arc<Mutex<ConnectionState>>,
     pub username: String,
-    pub room: i32,
     pub uuid: Uuid,
+    pub directory: Option<RoomDirectory>,
+    /// Message of the active room picked with the arrow keys in Normal mode.
+    pub selected: Option<u64>,
+    /// Message whose new text is being typed into the input.
+    pub editing: Option<u64>,
+    /// Message the next post answers.
+    pub replying: Option<u64>,
+    /// Message the emoji typed into the input reacts to.
+    pub reacting: Option<u64>,
+    /// Root of the conversation shown instead of the whole room, posts go to it as replies.
+    pub thread: Option<u64>,
+    last_typing: Option<Instant>,
+    /// Whether the newest message is on screen, set while drawing.
+    pub at_bottom: bool,
+    last_read_sent: Option<Instant>,
+    /// Shows who read the newest message of the room, toggled with /seen.
+    pub show_seen: bool,
+    /// Rooms with mentions that arrived while they were not on screen.
+    unseen_mentions: Vec<String>,
+    /// Matches offered by Tab for the word in front of the cursor.
+    pub completion: Option<Completion>,
+    /// Room names from the last /rooms listing, completed after /room.
+    known_rooms: Vec<String>,
 }
 
 pub enum InputMode {
@@ -35,9 +69,11 @@ pub enum InputMode {
 impl App {
     pub fn new(
         transmit: UnboundedSender<TalkProtocol>,
-        com: Arc<Mutex<Vec<TalkProtocol>>>,
+        incoming: UnboundedReceiver<TalkProtocol>,
+        com: Arc<Mutex<Rooms>>,
+        connection: Arc<Mutex<ConnectionState>>,
+        uuid: Uuid,
     ) -> Self {
-        let uuid = Self::load_or_create_uuid().unwrap_or_else(|_| Uuid::new_v4());
         Self {
             input: String::new(),
             input_mode: InputMode::Normal,
@@ -47,31 +83,129 @@ impl App {
             auto_scroll: true,
             character_index: 0,
             tx: transmit,
+            incoming,
+            connection,
             username: "Client".to_string(),
-            room: 0,
             uuid,
+            directory: None,
+            selected: None,
+            editing: None,
+            replying: None,
+            reacting: None,
+            thread: None,
+            last_typing: None,
+            at_bottom: true,
+            last_read_sent: None,
+            show_seen: false,
+            unseen_mentions: Vec::new(),
+            completion: None,
+            known_rooms: Vec::new(),
         }
     }
-    fn load_or_create_uuid() -> Result<Uuid, Box<dyn std::error::Error>> {
-        let config_dir = dirs::config_dir()
-            .ok_or("Cannot find config directory")?
-            .join("TuiTalk");
 
-        fs::create_dir_all(&config_dir)?;
+    pub fn supports(&self, capability: &str) -> bool {
+        self.connection
+            .lock()
+            .expect("Connection state")
+            .supports(capability)
+    }
 
-        let config_file = config_dir.join("uuid.cfg");
+    /// The room of the active tab.
+    pub fn room(&self) -> String {
+        self.communication
+            .lock()
+            .expect("Communication Vector")
+            .active_room()
+            .to_string()
+    }
 
-        if config_file.exists() {
-            let content = fs::read_to_string(&config_file)?;
-            if let Ok(uuid) = Uuid::parse_str(&content.trim()) {
-                return Ok(uuid);
+    // Frames that change the client itself are handled here, the rest is shown in the chat
+    fn receive(&mut self, msg: TalkProtocol) {
+        if let TalkProtocol::PostMessage { message } = &msg
+            && message.uuid != self.uuid
+            && message.mentions_user(self.uuid, &self.username)
+            && (!self.at_bottom || self.room() != message.room_id)
+        {
+            self.notify_mention(&message.room_id, &message.username);
+        }
+        let mut communication = self.communication.lock().expect("Communication Vector");
+        match msg {
+            TalkProtocol::LoggedIn {
+                username,
+                uuid,
+                token,
+            } => {
+                if let Err(e) = Identity::save_session_token(&token) {
+                    communication.push(TalkProtocol::LocalError {
+                        message: format!("Could not save login: {}", e),
+                    });
+                }
+                communication.push(TalkProtocol::LocalInformation {
+                    message: format!("Logged in as {}", username),
+                });
+                self.username = username;
+                self.uuid = uuid;
+            }
+            // the server announces the name it actually uses for us
+            TalkProtocol::UserJoined {
+                uuid, ref username, ..
             }
+            | TalkProtocol::UsernameChanged {
+                uuid, ref username, ..
+            } if uuid == self.uuid => {
+                self.username = username.clone();
+                communication.receive(msg);
+            }
+            TalkProtocol::RoomList { rooms } => {
+                self.known_rooms = rooms.iter().map(|room| room.display_name.clone()).collect();
+                self.directory = Some(RoomDirectory::new(rooms));
+            }
+            TalkProtocol::Typing { uuid, .. } | TalkProtocol::MarkRead { uuid, .. }
+                if uuid == self.uuid => {}
+            TalkProtocol::Error { ref code, .. }
+                if code == error_code::ROOM_PASSWORD_REQUIRED
+                    || code == error_code::ROOM_INVITE_ONLY
+                    || code == error_code::BANNED =>
+            {
+                // tell the connection to forget the refused room so it is not re-joined
+                if let Some(room_id) = communication.refuse() {
+                    let _ = self.tx.unbounded_send(TalkProtocol::LeaveRoom {
+                        room_id,
+                        uuid: self.uuid,
+                        username: self.username.clone(),
+                        unixtime: command::get_unix_timestamp().unwrap_or_default(),
+                    });
+                }
+                self.auto_scroll = true;
+                communication.receive(msg);
+            }
+            // a kicked or banned client closes the room's tab, the event is shown where it lands
+            TalkProtocol::Moderated {
+                ref room_id,
+                target_uuid,
+                action: ModerationAction::Kicked | ModerationAction::Banned,
+                ..
+            } if target_uuid == self.uuid => {
+                let room_id = room_id.clone();
+                if communication.close(&room_id) {
+                    let _ = self.tx.unbounded_send(TalkProtocol::LeaveRoom {
+                        room_id,
+                        uuid: self.uuid,
+                        username: self.username.clone(),
+                        unixtime: command::get_unix_timestamp().unwrap_or_default(),
+                    });
+                    self.auto_scroll = true;
+                    communication.push(msg);
+                } else {
+                    communication.receive(msg);
+                }
+            }
+            TalkProtocol::Error { ref code, .. } if code == error_code::INVALID_TOKEN => {
+                let _ = Identity::clear_session_token();
+                communication.receive(msg);
+            }
+            _ => communication.receive(msg),
         }
-
-        let new_uuid = Uuid::new_v4();
-        fs::write(&config_file, new_uuid.to_string())?;
-
-        Ok(new_uuid)
     }
 
     fn move_cursor_left(&mut self) {
@@ -88,6 +222,88 @@ impl App {
         let index = self.byte_index();
         self.input.insert(index, new_char);
         self.move_cursor_right();
+        self.notify_typing();
+    }
+
+    // With the bottom of the buffer on screen everything in it counts as read
+    fn mark_read(&mut self) {
+        if !self.supports(capability::RECEIPTS)
+            || !self.at_bottom
+            || self.thread.is_some()
+            || self
+                .last_read_sent
+                .is_some_and(|last| last.elapsed() < READ_INTERVAL)
+        {
+            return;
+        }
+        let Some((room_id, message_id)) = self
+            .communication
+            .lock()
+            .expect("Communication Vector")
+            .read_up_to()
+        else {
+            return;
+        };
+        self.last_read_sent = Some(Instant::now());
+        let _ = self.tx.unbounded_send(TalkProtocol::MarkRead {
+            room_id,
+            uuid: self.uuid,
+            username: self.username.clone(),
+            message_id,
+        });
+    }
+
+    // Rings the terminal bell and puts the mention into the window title, the room is not on screen
+    fn notify_mention(&mut self, room_id: &str, username: &str) {
+        if !self.unseen_mentions.iter().any(|room| room == room_id) {
+            self.unseen_mentions.push(room_id.to_string());
+        }
+        let title = format!(
+            "{} ({}) - {} mentioned you",
+            TITLE,
+            self.unseen_mentions.len(),
+            username
+        );
+        let _ = execute!(io::stdout(), Print('\x07'), SetTitle(title));
+    }
+
+    // Seeing the bottom of a room counts as having seen its mentions
+    fn clear_mentions(&mut self) {
+        if !self.at_bottom || self.unseen_mentions.is_empty() {
+            return;
+        }
+        let room_id = self.room();
+        let before = self.unseen_mentions.len();
+        self.unseen_mentions.retain(|room| *room != room_id);
+        if self.unseen_mentions.len() == before {
+            return;
+        }
+        let title = match self.unseen_mentions.len() {
+            0 => TITLE.to_string(),
+            unseen => format!("{} ({})", TITLE, unseen),
+        };
+        let _ = execute!(io::stdout(), SetTitle(title));
+    }
+
+    // Lets the room know a message is being written, commands, edits and reactions are not announced
+    fn notify_typing(&mut self) {
+        if !self.supports(capability::TYPING)
+            || self.editing.is_some()
+            || self.reacting.is_some()
+            || self.input.trim().is_empty()
+            || self.input.starts_with('/')
+            || self
+                .last_typing
+                .is_some_and(|last| last.elapsed() < TYPING_INTERVAL)
+        {
+            return;
+        }
+        self.last_typing = Some(Instant::now());
+        let _ = self.tx.unbounded_send(TalkProtocol::Typing {
+            room_id: self.room(),
+            uuid: self.uuid,
+            username: self.username.clone(),
+        });
     }
 
     fn byte_index(&self) -> usize {
@@ -115,12 +331,65 @@ impl App {
         new_cursor_pos.clamp(0, self.input.chars().count())
     }
 
+    // Tab fills in the first match, a single match is taken right away and further Tabs cycle
+    fn complete(&mut self, backwards: bool) {
+        let index = self.byte_index();
+        match &mut self.completion {
+            Some(completion) => completion.step(backwards),
+            None => {
+                let (usernames, mut rooms) = {
+                    let communication = self.communication.lock().expect("Communication Vector");
+                    let rooms: Vec<String> = communication
+                        .tabs
+                        .iter()
+                        .map(|tab| tab.display_name.clone())
+                        .collect();
+                    (communication.usernames(), rooms)
+                };
+                let usernames: Vec<String> = usernames
+                    .into_iter()
+                    .filter(|username| *username != self.username)
+                    .collect();
+                rooms.extend(self.known_rooms.iter().cloned());
+                let Some(mut completion) =
+                    Completion::new(&self.input[..index], &usernames, &rooms)
+                else {
+                    return;
+                };
+                if backwards {
+                    completion.step(true);
+                }
+                self.completion = Some(completion);
+            }
+        }
+        let Some(completion) = &self.completion else {
+            return;
+        };
+        let mut replacement = completion.current().to_string();
+        let start = completion.start;
+        if completion.candidates.len() == 1 {
+            replacement.push(' ');
+            self.completion = None;
+        }
+        self.input.replace_range(start..index, &replacement);
+        self.character_index = self.input[..start + replacement.len()].chars().count();
+    }
+
     fn reset_cursor(&mut self) {
         self.character_index = 0;
     }
 
     fn submit_message(&mut self) {
-        let _ = command::parse(self);
+        if let Some(message_id) = self.editing.take() {
+            let _ = command::edit_message(self, message_id);
+            self.input_mode = InputMode::Normal;
+        } else if let Some(message_id) = self.reacting.take() {
+            let _ = command::react(self, message_id);
+            self.input_mode = InputMode::Normal;
+        } else {
+            let _ = command::parse(self);
+            self.last_typing = None;
+        }
         self.input.clear();
         self.reset_cursor();
     }
@@ -129,84 +398,218 @@ impl App {
         let tick_rate = Duration::from_millis(100);
         let _ = command::join_initial_room(&mut self);
         loop {
+            while let Ok(Some(msg)) = self.incoming.try_next() {
+                self.receive(msg);
+            }
             terminal.draw(|frame| self.draw(frame))?;
+            self.mark_read();
+            self.clear_mentions();
 
             let last_tick = Instant::now();
             let timeout = tick_rate
                 .checked_sub(last_tick.elapsed())
                 .unwrap_or(Duration::from_secs(0));
 
-            if event::poll(timeout)? {
-                if let Event::Key(key) = event::read()? {
-                    match self.input_mode {
-                        InputMode::Normal => match key.code {
-                            KeyCode::Char('i') => {
+            if event::poll(timeout)?
+                && let Event::Key(key) = event::read()?
+            {
+                if self.directory.is_some() {
+                    if key.kind == KeyEventKind::Press {
+                        self.handle_directory_key(key.code);
+                    }
+                    continue;
+                }
+                match self.input_mode {
+                    InputMode::Normal => match key.code {
+                        KeyCode::Tab => self.switch_room(|rooms| rooms.next()),
+                        KeyCode::BackTab => self.switch_room(|rooms| rooms.previous()),
+                        KeyCode::Char(digit @ '1'..='9') => {
+                            let index = digit as usize - '1' as usize;
+                            self.switch_room(|rooms| rooms.select(index));
+                        }
+                        KeyCode::Char('i') => {
+                            self.input_mode = InputMode::Editing;
+                        }
+                        KeyCode::Up => self.select_message(true),
+                        KeyCode::Down => self.select_message(false),
+                        KeyCode::Esc => self.step_back(),
+                        KeyCode::Char('e') => self.start_edit(),
+                        KeyCode::Char('r') => {
+                            if let Some(message_id) = self.selected {
+                                self.replying = Some(message_id);
                                 self.input_mode = InputMode::Editing;
                             }
-                            KeyCode::Char('q') => {
-                                let _ = command::quit_app(&mut self);
-                                return Ok(());
+                        }
+                        KeyCode::Char('t') => self.open_thread(),
+                        KeyCode::Char('+') => {
+                            if let Some(message_id) = self.selected {
+                                self.reacting = Some(message_id);
+                                self.input_mode = InputMode::Editing;
                             }
-                            KeyCode::Char('g') => {
-                                self.scroll = self.max_scroll;
-                                self.auto_scroll = true;
+                        }
+                        KeyCode::Char('x') => {
+                            if let Some(message_id) = self.selected.take() {
+                                let _ = command::delete_message(&mut self, message_id);
                             }
-                            KeyCode::Char('G') => {
-                                self.auto_scroll = false;
-                                self.scroll = 0;
+                        }
+                        KeyCode::Char('q') => {
+                            let _ = command::quit_app(&mut self);
+                            return Ok(());
+                        }
+                        KeyCode::Char('g') => {
+                            self.scroll = self.max_scroll;
+                            self.auto_scroll = true;
+                        }
+                        KeyCode::Char('G') => {
+                            self.auto_scroll = false;
+                            self.scroll = 0;
+                        }
+                        KeyCode::Char('k') => {
+                            if self.scroll < self.max_scroll {
+                                self.scroll += DEFAULT_SCROLL;
                             }
-                            KeyCode::Char('k') => {
-                                if self.scroll < self.max_scroll {
-                                    self.scroll += DEFAULT_SCROLL;
-                                }
-                                if self.scroll >= self.max_scroll {
-                                    self.auto_scroll = true;
-                                }
+                            if self.scroll >= self.max_scroll {
+                                self.auto_scroll = true;
                             }
-                            KeyCode::Char('K') => {
-                                if self.max_scroll >= FAST_SCROLL
-                                    && self.scroll < self.max_scroll - FAST_SCROLL
-                                {
-                                    self.scroll += FAST_SCROLL;
-                                } else {
-                                    self.scroll = self.max_scroll;
-                                }
-                                if self.scroll >= self.max_scroll {
-                                    self.auto_scroll = true;
-                                }
+                        }
+                        KeyCode::Char('K') => {
+                            if self.max_scroll >= FAST_SCROLL
+                                && self.scroll < self.max_scroll - FAST_SCROLL
+                            {
+                                self.scroll += FAST_SCROLL;
+                            } else {
+                                self.scroll = self.max_scroll;
                             }
-                            KeyCode::Char('j') => {
-                                self.auto_scroll = false;
-                                if self.scroll > 0 {
-                                    self.scroll -= DEFAULT_SCROLL;
-                                }
+                            if self.scroll >= self.max_scroll {
+                                self.auto_scroll = true;
                             }
-                            KeyCode::Char('J') => {
-                                self.auto_scroll = false;
-                                if self.scroll > FAST_SCROLL {
-                                    self.scroll -= FAST_SCROLL;
-                                } else {
-                                    self.scroll = 0;
-                                }
+                        }
+                        KeyCode::Char('j') => {
+                            self.auto_scroll = false;
+                            if self.scroll > 0 {
+                                self.scroll -= DEFAULT_SCROLL;
                             }
-                            _ => {}
-                        },
-                        InputMode::Editing if key.kind == KeyEventKind::Press => match key.code {
+                        }
+                        KeyCode::Char('J') => {
+                            self.auto_scroll = false;
+                            if self.scroll > FAST_SCROLL {
+                                self.scroll -= FAST_SCROLL;
+                            } else {
+                                self.scroll = 0;
+                            }
+                        }
+                        _ => {}
+                    },
+                    InputMode::Editing if key.kind == KeyEventKind::Press => {
+                        // any key but Tab ends the completion, an Esc only closes its popup
+                        let closed_completion =
+                            !matches!(key.code, KeyCode::Tab | KeyCode::BackTab)
+                                && self.completion.take().is_some();
+                        match key.code {
+                            KeyCode::Tab => self.complete(false),
+                            KeyCode::BackTab => self.complete(true),
+                            KeyCode::Esc if closed_completion => {}
                             KeyCode::Enter => self.submit_message(),
                             KeyCode::Char(to_insert) => self.enter_char(to_insert),
                             KeyCode::Backspace => self.delete_char(),
                             KeyCode::Left => self.move_cursor_left(),
                             KeyCode::Right => self.move_cursor_right(),
-                            KeyCode::Esc => self.input_mode = InputMode::Normal,
+                            KeyCode::Esc => {
+                                // dropping an edit also drops its text
+                                if self.editing.take().is_some() || self.reacting.take().is_some() {
+                                    self.input.clear();
+                                    self.reset_cursor();
+                                }
+                                self.replying = None;
+                                self.input_mode = InputMode::Normal;
+                            }
                             _ => {}
-                        },
-                        InputMode::Editing => {}
+                        }
                     }
+                    InputMode::Editing => {}
                 }
             }
         }
     }
 
+    // The room list takes all keys while it is open
+    fn handle_directory_key(&mut self, code: KeyCode) {
+        let Some(directory) = &mut self.directory else {
+            return;
+        };
+        match code {
+            KeyCode::Down | KeyCode::Char('j') => directory.next(),
+            KeyCode::Up | KeyCode::Char('k') => directory.previous(),
+            KeyCode::Enter => {
+                let name = directory
+                    .selected_room()
+                    .map(|room| room.display_name.clone());
+                self.directory = None;
+                if let Some(name) = name {
+                    let _ = command::open_room(self, &name, None);
+                }
+            }
+            KeyCode::Esc | KeyCode::Char('q') => self.directory = None,
+            _ => {}
+        }
+    }
+
+    fn switch_room(&mut self, switch: impl FnOnce(&mut Rooms)) {
+        switch(&mut self.communication.lock().expect("Communication Vector"));
+        self.auto_scroll = true;
+        self.selected = None;
+        self.thread = None;
+    }
+
+    // Esc drops the selection first, then the thread view
+    fn step_back(&mut self) {
+        if self.selected.take().is_none() {
+            self.thread = None;
+            self.auto_scroll = true;
+        }
+    }
+
+    fn open_thread(&mut self) {
+        let Some(message_id) = self.selected.take() else {
+            return;
+        };
+        let root = self
+            .communication
+            .lock()
+            .expect("Communication Vector")
+            .thread_root(message_id);
+        self.thread = Some(root);
+        self.auto_scroll = true;
+    }
+
+    fn select_message(&mut self, older: bool) {
+        self.selected = self
+            .communication
+            .lock()
+            .expect("Communication Vector")
+            .step_selection(self.selected, older, self.thread);
+    }
+
+    // The selected message's text is loaded into the input, Enter sends the edit
+    fn start_edit(&mut self) {
+        let Some(message_id) = self.selected else {
+            return;
+        };
+        let text = self
+            .communication
+            .lock()
+            .expect("Communication Vector")
+            .message(message_id)
+            .filter(|message| message.uuid == self.uuid)
+            .map(|message| message.text.clone());
+        if let Some(text) = text {
+            self.character_index = text.chars().count();
+            self.input = text;
+            self.editing = Some(message_id);
+            self.input_mode = InputMode::Editing;
+        }
+    }
+
     fn draw(&mut self, frame: &mut ratatui::Frame) {
         ui::draw(self, frame);
     }
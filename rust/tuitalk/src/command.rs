@@ -1,86 +1,200 @@
 use crate::app;
+use crate::emoji::expand_shortcodes;
 use anyhow::{Context, Result};
-use tuitalk_shared::*;
 use std::{
     num::ParseIntError,
     time::{SystemTime, UNIX_EPOCH},
 };
+use tuitalk_shared::validation::{
+    MAX_MUTE_SECONDS, ValidationError, room_key, validate_message, validate_password,
+    validate_reaction, validate_room_name, validate_topic, validate_username,
+};
+use tuitalk_shared::*;
+use uuid::Uuid;
 
-const MESSAGE_LENGTH: usize = 250;
-const USERNAME_LENGTH: usize = 15;
+const CONVERSATION_FETCH_LIMIT: i64 = 50;
 
 pub fn get_unix_timestamp() -> Result<u64> {
     let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .context("unixtime")?;
-    Ok(now.as_secs())
+    Ok(now.as_millis() as u64)
 }
 
-pub fn get_first_message_timestamp(app: &mut app::App) -> Result<u64> {
+pub fn get_first_message_id(app: &mut app::App) -> Result<u64> {
     Ok(app
         .communication
         .lock()
         .expect("Vector of communication")
+        .messages()
         .iter()
-        .find_map(|proto| match proto {
-            TalkProtocol::Error { .. } => None,
-            TalkProtocol::LocalError { .. } => None,
-            TalkProtocol::LocalInformation { .. } => None,
-            TalkProtocol::PostMessage { message } => Some(message.unixtime),
-            TalkProtocol::UserJoined { unixtime, .. } => Some(*unixtime),
-            TalkProtocol::UserLeft { unixtime, .. } => Some(*unixtime),
-            TalkProtocol::UsernameChanged { unixtime, .. } => Some(*unixtime),
-            _ => None,
-        })
-        .unwrap_or(get_unix_timestamp()?))
+        .find_map(|proto| proto.message_id())
+        .unwrap_or(u64::MAX))
 }
 
 pub fn join_initial_room(app: &mut app::App) -> Result<()> {
-    let com = join_room(app);
+    let room_id = app.room();
+    let com = join_room(app, room_id, None);
     app.tx.unbounded_send(com?)?;
     Ok(())
 }
 
 pub fn quit_app(app: &mut app::App) -> Result<()> {
-    let com = leave_room(app);
-    app.tx.unbounded_send(com?)?;
+    let rooms: Vec<String> = app
+        .communication
+        .lock()
+        .expect("Communication Vector")
+        .tabs
+        .iter()
+        .map(|tab| tab.room_id.clone())
+        .collect();
+    for room_id in rooms {
+        let com = leave_room(app, room_id);
+        app.tx.unbounded_send(com?)?;
+    }
+    Ok(())
+}
+
+/// Switches to the room, joining it first if needed.
+pub fn open_room(app: &mut app::App, name: &str, password: Option<String>) -> Result<()> {
+    if let Some(join) = parse_command_room_valid(app, name, password)? {
+        app.tx.unbounded_send(join)?;
+    }
     Ok(())
 }
 
-pub fn join_room(app: &mut app::App) -> Result<TalkProtocol> {
+pub fn join_room(
+    app: &mut app::App,
+    room_id: String,
+    password: Option<String>,
+) -> Result<TalkProtocol> {
     Ok(TalkProtocol::JoinRoom {
-        room_id: app.room,
+        room_id,
         uuid: app.uuid,
         username: app.username.clone(),
         unixtime: get_unix_timestamp()?,
+        password,
     })
 }
 
-pub fn leave_room(app: &mut app::App) -> Result<TalkProtocol> {
+pub fn leave_room(app: &mut app::App, room_id: String) -> Result<TalkProtocol> {
     Ok(TalkProtocol::LeaveRoom {
-        room_id: app.room,
+        room_id,
         uuid: app.uuid,
         username: app.username.clone(),
         unixtime: get_unix_timestamp()?,
     })
 }
 
+/// Sends the input as the new text of the message.
+pub fn edit_message(app: &mut app::App, message_id: u64) -> Result<()> {
+    app.input = expand_shortcodes(&app.input);
+    if let Err(error) = validate_message(&app.input) {
+        let com = parse_invalid_input(error);
+        app.communication
+            .lock()
+            .expect("Communication Vector")
+            .push(com?);
+        return Ok(());
+    }
+    let com = TalkProtocol::EditMessage {
+        room_id: app.room(),
+        message_id,
+        uuid: app.uuid,
+        text: app.input.clone(),
+    };
+    app.tx.unbounded_send(com)?;
+    Ok(())
+}
+
+pub fn delete_message(app: &mut app::App, message_id: u64) -> Result<()> {
+    let com = TalkProtocol::DeleteMessage {
+        room_id: app.room(),
+        message_id,
+        uuid: app.uuid,
+    };
+    app.tx.unbounded_send(com)?;
+    Ok(())
+}
+
+/// Reacts with the emoji in the input, or takes the reaction back if it was already given.
+pub fn react(app: &mut app::App, message_id: u64) -> Result<()> {
+    let emoji = expand_shortcodes(app.input.trim());
+    if let Err(error) = validate_reaction(&emoji) {
+        let com = parse_invalid_input(error);
+        app.communication
+            .lock()
+            .expect("Communication Vector")
+            .push(com?);
+        return Ok(());
+    }
+    let reacted = app
+        .communication
+        .lock()
+        .expect("Communication Vector")
+        .message(message_id)
+        .is_some_and(|message| {
+            message
+                .reactions
+                .iter()
+                .any(|reaction| reaction.emoji == emoji && reaction.users.contains(&app.uuid))
+        });
+
+    let room_id = app.room();
+    let com = if reacted {
+        TalkProtocol::Unreact {
+            room_id,
+            message_id,
+            uuid: app.uuid,
+            emoji,
+        }
+    } else {
+        TalkProtocol::React {
+            room_id,
+            message_id,
+            uuid: app.uuid,
+            emoji,
+        }
+    };
+    app.tx.unbounded_send(com)?;
+    Ok(())
+}
+
+/// Commands known to `parse_command`, offered by Tab completion.
+pub const COMMANDS: &[&str] = &[
+    "ban", "clear", "fetch", "help", "invite", "kick", "leave", "lock", "login", "mentions", "mod",
+    "msg", "mute", "name", "register", "room", "rooms", "seen", "topic", "unban", "unlock",
+    "unmod", "who",
+];
+
 pub fn parse(app: &mut app::App) -> Result<()> {
+    if !app.input.starts_with("/") {
+        app.input = expand_shortcodes(&app.input);
+    }
     if app.input.is_empty() {
-    } else if app.input.len() >= MESSAGE_LENGTH {
-        let com = parse_message_too_long();
-        app.tx.unbounded_send(com?)?;
     } else if app.input.starts_with("/") {
         app.input = app.input.trim_start_matches("/").trim().to_string();
         let _ = parse_command(app);
+    } else if let Err(error) = validate_message(&app.input) {
+        let com = parse_invalid_input(error);
+        app.communication
+            .lock()
+            .expect("Communication Vector")
+            .push(com?);
     } else {
         let com = TalkProtocol::PostMessage {
             message: TalkMessage {
                 uuid: app.uuid,
                 username: app.username.to_string(),
                 text: app.input.to_string(),
-                room_id: app.room,
+                room_id: app.room(),
                 unixtime: get_unix_timestamp()?,
+                message_id: 0,
+                reply_to: app.replying.take().or(app.thread),
+                edited: false,
+                deleted: false,
+                reactions: Vec::new(),
+                mentions: Vec::new(),
             },
         };
         app.tx.unbounded_send(com)?;
@@ -91,11 +205,21 @@ pub fn parse(app: &mut app::App) -> Result<()> {
 fn parse_command(app: &mut app::App) -> Result<()> {
     if app.input.starts_with("name") {
         app.input = app.input.trim_start_matches("name ").trim().to_string();
-        if app.input.len() <= USERNAME_LENGTH {
+        if let Err(error) = validate_username(&app.input) {
+            let com = parse_invalid_input(error);
+            app.communication
+                .lock()
+                .expect("Communication Vector")
+                .push(com?);
+        } else {
             let com = parse_command_name(app);
             app.tx.unbounded_send(com?)?;
+        }
+    } else if app.input == "rooms" {
+        if app.supports(capability::DIRECTORY) {
+            app.tx.unbounded_send(TalkProtocol::ListRooms)?;
         } else {
-            let com = parse_message_too_long();
+            let com = parse_unsupported_command("rooms");
             app.communication
                 .lock()
                 .expect("Communication Vector")
@@ -103,29 +227,141 @@ fn parse_command(app: &mut app::App) -> Result<()> {
         }
     } else if app.input.starts_with("room") {
         app.input = app.input.trim_start_matches("room").trim().to_string();
-        match app.input.parse::<i32>() {
-            Ok(number) => {
-                let (leave, join) = parse_command_room_valid(app, number)?;
-                app.tx.unbounded_send(join)?;
-                app.communication
-                    .lock()
-                    .expect("Communication Vector")
-                    .clear();
-                app.tx.unbounded_send(leave)?;
-            }
+        let (name, password) = match app.input.split_once(char::is_whitespace) {
+            Some((name, password)) => (name.to_string(), Some(password.trim().to_string())),
+            None => (app.input.clone(), None),
+        };
+        match validate_room_name(&name) {
+            Ok(()) => open_room(app, &name, password)?,
             Err(error) => {
-                let com = parse_command_room_invalid(error);
+                let com = parse_invalid_input(error);
                 app.communication
                     .lock()
                     .expect("Communication Vector")
                     .push(com?);
             }
         }
+    } else if app.input.starts_with("register ") || app.input.starts_with("login ") {
+        let com = parse_command_account(app);
+        match com? {
+            com @ TalkProtocol::LocalError { .. } => app
+                .communication
+                .lock()
+                .expect("Communication Vector")
+                .push(com),
+            com => app.tx.unbounded_send(com)?,
+        }
+    } else if app.input == "topic" || app.input.starts_with("topic ") {
+        let com = parse_command_topic(app);
+        match com? {
+            com @ (TalkProtocol::LocalError { .. } | TalkProtocol::LocalInformation { .. }) => app
+                .communication
+                .lock()
+                .expect("Communication Vector")
+                .push(com),
+            com => app.tx.unbounded_send(com)?,
+        }
+    } else if app.input == "lock" || app.input.starts_with("lock ") || app.input == "unlock" {
+        let com = parse_command_lock(app);
+        app.tx.unbounded_send(com?)?;
+    } else if app.input == "invite" || app.input.starts_with("invite ") {
+        let com = parse_command_invite(app);
+        match com? {
+            com @ TalkProtocol::LocalError { .. } => app
+                .communication
+                .lock()
+                .expect("Communication Vector")
+                .push(com),
+            com => app.tx.unbounded_send(com)?,
+        }
+    } else if ["kick", "ban", "unban", "mute", "mod", "unmod"]
+        .iter()
+        .any(|command| app.input.split_whitespace().next() == Some(command))
+    {
+        let com = parse_command_moderation(app);
+        match com? {
+            com @ TalkProtocol::LocalError { .. } => app
+                .communication
+                .lock()
+                .expect("Communication Vector")
+                .push(com),
+            com => app.tx.unbounded_send(com)?,
+        }
+    } else if app.input == "leave" {
+        let com = parse_command_leave(app);
+        match com? {
+            com @ TalkProtocol::LocalError { .. } => app
+                .communication
+                .lock()
+                .expect("Communication Vector")
+                .push(com),
+            com => app.tx.unbounded_send(com)?,
+        }
+    } else if app.input == "seen" {
+        app.show_seen = !app.show_seen;
+        let message = if app.show_seen {
+            "Showing who has seen the latest message".to_string()
+        } else {
+            "No longer showing who has seen the latest message".to_string()
+        };
+        app.communication
+            .lock()
+            .expect("Communication Vector")
+            .push(TalkProtocol::LocalInformation { message });
+    } else if app.input == "mentions" {
+        let mut communication = app.communication.lock().expect("Communication Vector");
+        let mentions: Vec<String> = communication
+            .mentions(app.uuid, &app.username)
+            .iter()
+            .map(|(room, message)| format!("[{}] {}: {}", room, message.username, message.text))
+            .collect();
+        if mentions.is_empty() {
+            communication.push(TalkProtocol::LocalInformation {
+                message: "Nobody mentioned you in your rooms yet".to_string(),
+            });
+        } else {
+            // one entry per mention, a line break inside an entry is not shown
+            communication.push(TalkProtocol::LocalInformation {
+                message: "Mentions:".to_string(),
+            });
+            for message in mentions {
+                communication.push(TalkProtocol::LocalInformation { message });
+            }
+        }
     } else if app.input == "clear" {
         app.communication
             .lock()
             .expect("Communication Vector")
             .clear();
+    } else if app.input == "who" {
+        if app.supports(capability::PRESENCE) {
+            let com = parse_command_who(app);
+            app.tx.unbounded_send(com?)?;
+        } else {
+            let com = parse_unsupported_command("who");
+            app.communication
+                .lock()
+                .expect("Communication Vector")
+                .push(com?);
+        }
+    } else if app.input == "msg" || app.input.starts_with("msg ") {
+        if app.supports(capability::DIRECT) {
+            let com = parse_command_msg(app);
+            match com? {
+                com @ TalkProtocol::LocalError { .. } => app
+                    .communication
+                    .lock()
+                    .expect("Communication Vector")
+                    .push(com),
+                com => app.tx.unbounded_send(com)?,
+            }
+        } else {
+            let com = parse_unsupported_command("msg");
+            app.communication
+                .lock()
+                .expect("Communication Vector")
+                .push(com?);
+        }
     } else if app.input == "help" {
         let com = parse_help();
         app.communication
@@ -135,6 +371,13 @@ fn parse_command(app: &mut app::App) -> Result<()> {
     } else if app.input.starts_with("fetch") {
         app.input = app.input.trim_start_matches("fetch").trim().to_string();
         match app.input.parse::<i64>() {
+            Ok(_) if !app.supports(capability::HISTORY) => {
+                let com = parse_unsupported_command("fetch");
+                app.communication
+                    .lock()
+                    .expect("Communication Vector")
+                    .push(com?);
+            }
             Ok(number) => {
                 let com = parse_command_fetch_valid(app, number);
                 app.tx.unbounded_send(com?)?;
@@ -157,30 +400,187 @@ fn parse_command(app: &mut app::App) -> Result<()> {
     Ok(())
 }
 
+fn parse_unsupported_command(command: &str) -> Result<TalkProtocol> {
+    Ok(TalkProtocol::LocalError {
+        message: format!("The server does not support '/{}'", command),
+    })
+}
+
+// Joined rooms are only switched to, new ones are joined in their own tab
 fn parse_command_room_valid(
     app: &mut app::App,
-    number: i32,
-) -> Result<(TalkProtocol, TalkProtocol)> {
-    let leave_message = leave_room(app)?;
-    app.room = number;
-    let join_message = join_room(app)?;
-    Ok((leave_message, join_message))
+    name: &str,
+    password: Option<String>,
+) -> Result<Option<TalkProtocol>> {
+    app.auto_scroll = true;
+    let joined = {
+        let mut rooms = app.communication.lock().expect("Communication Vector");
+        let joined = rooms.contains(&room_key(name));
+        rooms.open(name);
+        joined
+    };
+    if joined {
+        return Ok(None);
+    }
+    Ok(Some(join_room(app, name.to_string(), password)?))
 }
 
-fn parse_command_room_invalid(error: ParseIntError) -> Result<TalkProtocol> {
-    Ok(TalkProtocol::LocalError {
-        message: error.to_string(),
+// With a password the room asks for it, without one only invited users get in
+fn parse_command_lock(app: &mut app::App) -> Result<TalkProtocol> {
+    let password = app.input.trim_start_matches("lock").trim();
+    let (access, password) = match app.input.as_str() {
+        "unlock" => (RoomAccess::Public, None),
+        "lock" => (RoomAccess::InviteOnly, None),
+        _ => (RoomAccess::Password, Some(password.to_string())),
+    };
+    Ok(TalkProtocol::SetRoomAccess {
+        room_id: app.room(),
+        access,
+        password,
+    })
+}
+
+// Kick and ban take an optional reason, mute a duration like 30s, 10m, 2h or 1d
+fn parse_command_moderation(app: &mut app::App) -> Result<TalkProtocol> {
+    let mut parts = app.input.splitn(3, char::is_whitespace);
+    let command = parts.next().unwrap_or_default();
+    let Some(username) = parts.next().filter(|username| !username.is_empty()) else {
+        return Ok(TalkProtocol::LocalError {
+            message: format!("Usage: /{} {{name}}", command),
+        });
+    };
+    if let Err(error) = validate_username(username) {
+        return parse_invalid_input(error);
+    }
+    let rest = parts.next().map(str::trim).filter(|rest| !rest.is_empty());
+    if let Some(reason) = rest
+        && let Err(error) = validate_message(reason)
+    {
+        return parse_invalid_input(error);
+    }
+
+    let room_id = app.room();
+    let username = username.to_string();
+    let reason = rest.map(str::to_string);
+    Ok(match command {
+        "kick" => TalkProtocol::Kick {
+            room_id,
+            username,
+            reason,
+        },
+        "ban" => TalkProtocol::Ban {
+            room_id,
+            username,
+            reason,
+        },
+        "unban" => TalkProtocol::Unban { room_id, username },
+        "mute" => match rest.and_then(parse_duration) {
+            Some(duration) => TalkProtocol::Mute {
+                room_id,
+                username,
+                duration,
+            },
+            None => TalkProtocol::LocalError {
+                message: "Usage: /mute {name} {duration}, for example 30s, 10m, 2h or 1d"
+                    .to_string(),
+            },
+        },
+        "mod" => TalkProtocol::SetRole {
+            room_id,
+            username,
+            role: RoomRole::Moderator,
+        },
+        _ => TalkProtocol::SetRole {
+            room_id,
+            username,
+            role: RoomRole::Member,
+        },
+    })
+}
+
+// Seconds in a duration like 90, 30s, 10m, 2h or 1d, longer mutes than the server allows are cut short
+fn parse_duration(duration: &str) -> Option<u64> {
+    let (number, unit) = match duration.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => duration.split_at(index),
+        None => (duration, "s"),
+    };
+    let factor = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    let seconds = number.parse::<u64>().ok()?.checked_mul(factor)?;
+    Some(seconds.min(MAX_MUTE_SECONDS))
+}
+
+fn parse_command_invite(app: &mut app::App) -> Result<TalkProtocol> {
+    let username = app.input.trim_start_matches("invite").trim();
+    if username.is_empty() {
+        return Ok(TalkProtocol::LocalError {
+            message: "Usage: /invite {name}".to_string(),
+        });
+    }
+    if let Err(error) = validate_username(username) {
+        return parse_invalid_input(error);
+    }
+
+    Ok(TalkProtocol::Invite {
+        room_id: app.room(),
+        username: username.to_string(),
     })
 }
 
+fn parse_command_leave(app: &mut app::App) -> Result<TalkProtocol> {
+    let room_id = app.room();
+    let closed = app
+        .communication
+        .lock()
+        .expect("Communication Vector")
+        .close(&room_id);
+    if !closed {
+        return Ok(TalkProtocol::LocalError {
+            message: "You can't leave your last room, join another one first".to_string(),
+        });
+    }
+    app.auto_scroll = true;
+    leave_room(app, room_id)
+}
+
+// Without a text the current topic is shown
+fn parse_command_topic(app: &mut app::App) -> Result<TalkProtocol> {
+    let topic = app.input.trim_start_matches("topic").trim();
+    if topic.is_empty() {
+        let rooms = app.communication.lock().expect("Communication Vector");
+        let tab = rooms.active_tab();
+        let message = match &tab.topic {
+            Some(topic) => format!("The topic of {} is: {}", tab.display_name, topic),
+            None => format!(
+                "{} has no topic, set one with /topic {{text}}",
+                tab.display_name
+            ),
+        };
+        return Ok(TalkProtocol::LocalInformation { message });
+    }
+    if let Err(error) = validate_topic(topic) {
+        return parse_invalid_input(error);
+    }
+
+    Ok(TalkProtocol::SetTopic {
+        room_id: app.room(),
+        uuid: app.uuid,
+        topic: topic.to_string(),
+    })
+}
+
+// The name is only taken over once the server announces it, it may refuse it
 fn parse_command_name(app: &mut app::App) -> Result<TalkProtocol> {
-    let old_username = app.username.to_string();
-    app.username = app.input.to_string();
     Ok(TalkProtocol::ChangeName {
-        room_id: app.room,
+        room_id: app.room(),
         uuid: app.uuid,
-        username: app.username.to_string(),
-        old_username: old_username.to_string(),
+        username: app.input.to_string(),
+        old_username: app.username.to_string(),
         unixtime: get_unix_timestamp()?,
     })
 }
@@ -193,9 +593,79 @@ fn parse_invalid_command(app: &mut app::App) -> Result<TalkProtocol> {
 
 fn parse_command_fetch_valid(app: &mut app::App, set_limit: i64) -> Result<TalkProtocol> {
     Ok(TalkProtocol::Fetch {
-        room_id: app.room,
+        room_id: app.room(),
         limit: set_limit,
-        fetch_before: get_first_message_timestamp(app)?,
+        fetch_before: get_first_message_id(app)?,
+    })
+}
+
+fn parse_command_account(app: &mut app::App) -> Result<TalkProtocol> {
+    let mut parts = app.input.split_whitespace();
+    let (Some(command), Some(username), Some(password), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Ok(TalkProtocol::LocalError {
+            message: "Usage: /register {name} {password} or /login {name} {password}".to_string(),
+        });
+    };
+    if let Err(error) = validate_username(username) {
+        return parse_invalid_input(error);
+    }
+    // accounts from before the length rules still log in with their password
+    if command == "register"
+        && let Err(error) = validate_password(password)
+    {
+        return parse_invalid_input(error);
+    }
+
+    let username = username.to_string();
+    let password = password.to_string();
+    Ok(match command {
+        "register" => TalkProtocol::Register { username, password },
+        _ => TalkProtocol::Login { username, password },
+    })
+}
+
+// Without a text the conversation with the user is shown instead
+fn parse_command_msg(app: &mut app::App) -> Result<TalkProtocol> {
+    let rest = app.input.trim_start_matches("msg").trim();
+    let (recipient, text) = match rest.split_once(char::is_whitespace) {
+        Some((recipient, text)) => (recipient, text.trim()),
+        None => (rest, ""),
+    };
+    if recipient.is_empty() {
+        return Ok(TalkProtocol::LocalError {
+            message: "Usage: /msg {name} {text} or /msg {name} to show the conversation"
+                .to_string(),
+        });
+    }
+    if let Err(error) = validate_username(recipient) {
+        return parse_invalid_input(error);
+    }
+    if text.is_empty() {
+        return Ok(TalkProtocol::FetchConversation {
+            username: recipient.to_string(),
+            limit: CONVERSATION_FETCH_LIMIT,
+        });
+    }
+    if let Err(error) = validate_message(text) {
+        return parse_invalid_input(error);
+    }
+
+    Ok(TalkProtocol::DirectMessage {
+        uuid: app.uuid,
+        username: app.username.clone(),
+        recipient_uuid: Uuid::nil(),
+        recipient: recipient.to_string(),
+        text: text.to_string(),
+        unixtime: get_unix_timestamp()?,
+        message_id: 0,
+    })
+}
+
+fn parse_command_who(app: &mut app::App) -> Result<TalkProtocol> {
+    Ok(TalkProtocol::Who {
+        room_id: app.room(),
     })
 }
 
@@ -205,9 +675,9 @@ fn parse_command_fetch_invalid(error: ParseIntError) -> Result<TalkProtocol> {
     })
 }
 
-fn parse_message_too_long() -> Result<TalkProtocol> {
+fn parse_invalid_input(error: ValidationError) -> Result<TalkProtocol> {
     Ok(TalkProtocol::LocalError {
-        message: "Input too long".to_string(),
+        message: error.to_string(),
     })
 }
 
@@ -215,9 +685,56 @@ fn parse_help() -> Result<TalkProtocol> {
     Ok(TalkProtocol::LocalInformation {
         message: "\n/help to show this command\n
         /name {string} changes the name to the given string\n
-        /room {int} changes the room to the given number\n
+        /room {name} joins the given room in a new tab or switches to it\n
+        /room {name} {password} joins a password protected room\n
+        /rooms lists active rooms, Enter joins the selected one\n
+        /topic {text} sets the topic of the current room, /topic shows it\n
+        /lock {password} protects the current room with a password, /lock makes it invite only\n
+        /unlock opens the current room to everyone again\n
+        /invite {name} lets the user join the current room\n
+        /kick {name} {reason} removes the user from the current room, the reason is optional\n
+        /ban {name} {reason} kicks the user and keeps them out, /unban {name} lets them back in\n
+        /mute {name} {duration} silences the user for e.g. 30s, 10m, 2h or 1d, 0 lifts the mute\n
+        /mod {name} makes the user a moderator of the current room, /unmod {name} takes it back\n
+        /leave leaves the current room and closes its tab\n
         /fetch {number} fetches the given number of messages up from the first message in your history\n
+        /who lists the users in the current room\n
+        /msg {name} {text} sends a direct message, /msg {name} shows your conversation\n
+        /register {name} {password} creates an account that owns the name\n
+        /login {name} {password} logs in to your account\n
+        /seen toggles showing who has seen the latest message\n
+        /mentions lists the latest messages mentioning you in your rooms\n
         /clear clears the chat\n"
             .to_string(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_take_a_unit_or_default_to_seconds() {
+        assert_eq!(parse_duration("45"), Some(45));
+        assert_eq!(parse_duration("30s"), Some(30));
+        assert_eq!(parse_duration("10m"), Some(600));
+        assert_eq!(parse_duration("2h"), Some(7200));
+        assert_eq!(parse_duration("1d"), Some(86400));
+        assert_eq!(parse_duration("0"), Some(0));
+    }
+
+    #[test]
+    fn durations_are_capped_at_the_longest_mute() {
+        assert_eq!(parse_duration("365d"), Some(MAX_MUTE_SECONDS));
+        assert_eq!(parse_duration("400d"), Some(MAX_MUTE_SECONDS));
+        assert_eq!(parse_duration("10000000000000"), Some(MAX_MUTE_SECONDS));
+    }
+
+    #[test]
+    fn malformed_durations_are_rejected() {
+        for duration in ["", "m", "5x", "1.5h", "-1m", "10 m", "10M"] {
+            assert_eq!(parse_duration(duration), None, "{}", duration);
+        }
+        assert_eq!(parse_duration(&format!("{}d", u64::MAX)), None);
+    }
+}
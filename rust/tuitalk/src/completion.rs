@@ -0,0 +1,136 @@
+use crate::command::COMMANDS;
+
+// Commands whose first argument is the name of a user
+const USER_COMMANDS: &[&str] = &[
+    "msg", "invite", "kick", "ban", "unban", "mute", "mod", "unmod",
+];
+
+/// Matches for the word in front of the cursor, Tab cycles through them.
+pub struct Completion {
+    /// Byte offset in the input where the completed word starts.
+    pub start: usize,
+    pub candidates: Vec<String>,
+    pub selected: usize,
+}
+
+impl Completion {
+    /// Finds what the word in front of the cursor can become: a command at the start of the input,
+    /// a room after `/room`, a user after `@` or as the first argument of a command that takes one.
+    pub fn new(before_cursor: &str, usernames: &[String], rooms: &[String]) -> Option<Self> {
+        // the whitespace can be wider than a byte, the word starts after all of it
+        let start = before_cursor
+            .char_indices()
+            .rfind(|(_, c)| c.is_whitespace())
+            .map_or(0, |(index, c)| index + c.len_utf8());
+        let word = &before_cursor[start..];
+        let arguments: Vec<&str> = before_cursor[..start].split_whitespace().collect();
+
+        let candidates = if let Some(command) = word.strip_prefix('/').filter(|_| start == 0) {
+            matching(COMMANDS.iter().copied(), command, "/")
+        } else if let Some(name) = word.strip_prefix('@') {
+            matching(usernames.iter().map(String::as_str), name, "@")
+        } else if arguments == ["/room"] {
+            matching(rooms.iter().map(String::as_str), word, "")
+        } else if let [command] = arguments[..]
+            && command
+                .strip_prefix('/')
+                .is_some_and(|command| USER_COMMANDS.contains(&command))
+        {
+            matching(usernames.iter().map(String::as_str), word, "")
+        } else {
+            Vec::new()
+        };
+
+        (!candidates.is_empty()).then_some(Self {
+            start,
+            candidates,
+            selected: 0,
+        })
+    }
+
+    pub fn current(&self) -> &str {
+        &self.candidates[self.selected]
+    }
+
+    pub fn step(&mut self, backwards: bool) {
+        let len = self.candidates.len();
+        self.selected = if backwards {
+            (self.selected + len - 1) % len
+        } else {
+            (self.selected + 1) % len
+        };
+    }
+}
+
+// Names starting with the typed prefix, ignoring case, sorted and each one once
+fn matching<'a>(names: impl Iterator<Item = &'a str>, prefix: &str, sigil: &str) -> Vec<String> {
+    let prefix = prefix.to_lowercase();
+    let mut candidates: Vec<String> = names
+        .filter(|name| name.to_lowercase().starts_with(&prefix))
+        .map(|name| format!("{}{}", sigil, name))
+        .collect();
+    candidates.sort_by_key(|candidate| candidate.to_lowercase());
+    candidates.dedup();
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn completes_commands_at_the_start() {
+        let completion = Completion::new("/mu", &[], &[]).unwrap();
+        assert_eq!(completion.start, 0);
+        assert_eq!(completion.candidates, ["/mute"]);
+        assert!(Completion::new("hi /mu", &[], &[]).is_none());
+    }
+
+    #[test]
+    fn completes_mentions_ignoring_case() {
+        let usernames = names(&["bob", "Alice", "alfred"]);
+        let completion = Completion::new("hey @al", &usernames, &[]).unwrap();
+        assert_eq!(completion.start, 4);
+        assert_eq!(completion.candidates, ["@alfred", "@Alice"]);
+    }
+
+    #[test]
+    fn completes_rooms_and_users_as_arguments() {
+        let rooms = names(&["lobby", "rust"]);
+        let usernames = names(&["bob"]);
+        assert_eq!(
+            Completion::new("/room l", &[], &rooms).unwrap().candidates,
+            ["lobby"]
+        );
+        assert_eq!(
+            Completion::new("/kick b", &usernames, &[])
+                .unwrap()
+                .candidates,
+            ["bob"]
+        );
+        assert!(Completion::new("/kick bob b", &usernames, &[]).is_none());
+        assert!(Completion::new("/topic b", &usernames, &[]).is_none());
+    }
+
+    #[test]
+    fn word_after_wide_whitespace_starts_on_a_char_boundary() {
+        let usernames = names(&["bob"]);
+        let completion = Completion::new("hi\u{a0}@b", &usernames, &[]).unwrap();
+        assert_eq!(completion.start, "hi\u{a0}".len());
+        assert_eq!(completion.candidates, ["@bob"]);
+    }
+
+    #[test]
+    fn step_wraps_around() {
+        let usernames = names(&["ann", "anna"]);
+        let mut completion = Completion::new("@an", &usernames, &[]).unwrap();
+        completion.step(true);
+        assert_eq!(completion.current(), "@anna");
+        completion.step(false);
+        assert_eq!(completion.current(), "@ann");
+    }
+}
@@ -0,0 +1,217 @@
+use crate::command::get_unix_timestamp;
+use crate::identity::Identity;
+use crate::rooms::Rooms;
+use futures_channel::mpsc::{UnboundedReceiver, UnboundedSender};
+use futures_util::stream::FusedStream;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tuitalk_shared::native::{SendPacer, connect, handshake, receiver_task, sender_task};
+use tuitalk_shared::validation::room_key;
+use tuitalk_shared::*;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+const RESUME_FETCH_LIMIT: i64 = 100;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConnectionState {
+    Connecting,
+    Handshaking,
+    Connected { capabilities: Vec<String> },
+    Reconnecting { attempt: u32 },
+    Rejected { reason: String },
+}
+
+impl ConnectionState {
+    pub fn supports(&self, capability: &str) -> bool {
+        match self {
+            ConnectionState::Connected { capabilities } => {
+                capabilities.iter().any(|c| c == capability)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn hello() -> TalkProtocol {
+    TalkProtocol::Hello {
+        protocol_version: PROTOCOL_VERSION,
+        client_name: format!("tuitalk {}", env!("CARGO_PKG_VERSION")),
+        capabilities: capability::ALL.iter().map(|c| c.to_string()).collect(),
+    }
+}
+
+// The rooms the client joined, re-joined after a reconnect
+#[derive(Default)]
+struct Resume {
+    joins: Vec<TalkProtocol>,
+}
+
+impl Resume {
+    fn track(&mut self, msg: &TalkProtocol) {
+        match msg {
+            TalkProtocol::JoinRoom { room_id, .. } => {
+                self.forget(&room_key(room_id));
+                self.joins.push(msg.clone());
+            }
+            TalkProtocol::LeaveRoom { room_id, .. } => self.forget(room_id),
+            // a rename counts once the server announced it, it may refuse the name
+            TalkProtocol::UsernameChanged {
+                uuid: renamed,
+                username: new_name,
+                ..
+            } => {
+                for join in &mut self.joins {
+                    if let TalkProtocol::JoinRoom { uuid, username, .. } = join
+                        && uuid == renamed
+                    {
+                        *username = new_name.clone();
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn forget(&mut self, room_id: &str) {
+        self.joins.retain(|join| {
+            !matches!(join, TalkProtocol::JoinRoom { room_id: joined, .. } if room_key(joined) == room_id)
+        });
+    }
+
+    fn frames(&self, rooms: &Rooms, can_resume: bool) -> Vec<TalkProtocol> {
+        let mut frames = Vec::new();
+        for join in &self.joins {
+            let TalkProtocol::JoinRoom {
+                room_id,
+                uuid,
+                username,
+                password,
+                ..
+            } = join
+            else {
+                continue;
+            };
+
+            frames.push(TalkProtocol::JoinRoom {
+                room_id: room_id.clone(),
+                uuid: *uuid,
+                username: username.clone(),
+                unixtime: get_unix_timestamp().unwrap_or_default(),
+                password: password.clone(),
+            });
+            if let Some(fetch_after) = rooms.last_seen(&room_key(room_id))
+                && can_resume
+            {
+                frames.push(TalkProtocol::FetchAfter {
+                    room_id: room_key(room_id),
+                    limit: RESUME_FETCH_LIMIT,
+                    fetch_after,
+                });
+            }
+        }
+        frames
+    }
+}
+
+// Turns the outcome of the handshake into the connection state, false if the server refused us
+fn handle_handshake(
+    state: &Mutex<ConnectionState>,
+    incoming: &UnboundedSender<TalkProtocol>,
+    msg: TalkProtocol,
+) -> bool {
+    match msg {
+        TalkProtocol::Welcome {
+            capabilities, motd, ..
+        } => {
+            *state.lock().expect("Connection state") = ConnectionState::Connected { capabilities };
+            if let Some(message) = motd {
+                let _ = incoming.unbounded_send(TalkProtocol::LocalInformation { message });
+            }
+            true
+        }
+        TalkProtocol::Error { code, message } => {
+            *state.lock().expect("Connection state") = ConnectionState::Rejected {
+                reason: message.clone(),
+            };
+            let _ = incoming.unbounded_send(TalkProtocol::Error { code, message });
+            false
+        }
+        _ => false,
+    }
+}
+
+/// Keeps the WebSocket alive for the lifetime of the app and hands every received frame to `incoming`.
+/// Messages typed while offline stay queued and are flushed after the room was re-joined.
+pub async fn connection_task(
+    url: String,
+    mut rx: UnboundedReceiver<TalkProtocol>,
+    incoming: UnboundedSender<TalkProtocol>,
+    communication: Arc<Mutex<Rooms>>,
+    state: Arc<Mutex<ConnectionState>>,
+    identity: Identity,
+) {
+    let mut pending: VecDeque<TalkProtocol> = VecDeque::new();
+    // sent joins and received renames both update it
+    let resume = Mutex::new(Resume::default());
+    let mut pacer = SendPacer::default();
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 0;
+
+    loop {
+        if let Ok((mut write, mut read)) = connect(url.clone()).await {
+            *state.lock().expect("Connection state") = ConnectionState::Handshaking;
+
+            let outcome = handshake(&mut write, &mut read, hello(), |challenge| {
+                identity.authenticate(challenge)
+            })
+            .await;
+            if let Ok(outcome) = outcome {
+                if !handle_handshake(&state, &incoming, outcome) {
+                    return;
+                }
+                backoff = INITIAL_BACKOFF;
+
+                if attempt > 0 {
+                    let can_resume = state
+                        .lock()
+                        .expect("Connection state")
+                        .supports(capability::RESUME);
+                    let frames = resume.lock().expect("Resume").frames(
+                        &communication.lock().expect("Communication Vector"),
+                        can_resume,
+                    );
+                    for frame in frames.into_iter().rev() {
+                        pending.push_front(frame);
+                    }
+                }
+                // restore the account before anything is sent in its name
+                if let Some(token) = Identity::load_session_token() {
+                    pending.push_front(TalkProtocol::TokenLogin { token });
+                }
+                attempt = 0;
+
+                tokio::select! {
+                    _ = sender_task(&mut rx, &mut pending, &mut pacer, write, |msg| {
+                        resume.lock().expect("Resume").track(msg)
+                    }) => {},
+                    _ = receiver_task(read, |msg| {
+                        resume.lock().expect("Resume").track(&msg);
+                        let _ = incoming.unbounded_send(msg);
+                    }) => {},
+                }
+
+                // the app dropped its sender, nothing left to deliver
+                if rx.is_terminated() {
+                    return;
+                }
+            }
+        }
+
+        attempt += 1;
+        *state.lock().expect("Connection state") = ConnectionState::Reconnecting { attempt };
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
@@ -0,0 +1,86 @@
+// Shortcodes that can be typed as :name: instead of the emoji itself
+const SHORTCODES: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("100", "💯"),
+    ("check", "✅"),
+    ("clap", "👏"),
+    ("cry", "😢"),
+    ("eyes", "👀"),
+    ("fire", "🔥"),
+    ("grin", "😁"),
+    ("heart", "❤️"),
+    ("joy", "😂"),
+    ("ok", "👌"),
+    ("party", "🥳"),
+    ("pray", "🙏"),
+    ("rocket", "🚀"),
+    ("sad", "🙁"),
+    ("smile", "😄"),
+    ("star", "⭐"),
+    ("tada", "🎉"),
+    ("thinking", "🤔"),
+    ("thumbsdown", "👎"),
+    ("thumbsup", "👍"),
+    ("wave", "👋"),
+    ("wink", "😉"),
+    ("x", "❌"),
+];
+
+/// The emoji for a shortcode without its colons.
+pub fn shortcode(name: &str) -> Option<&'static str> {
+    SHORTCODES
+        .iter()
+        .find(|(code, _)| code.eq_ignore_ascii_case(name))
+        .map(|(_, emoji)| *emoji)
+}
+
+/// Replaces every known `:shortcode:` in the text, unknown ones are kept as typed.
+pub fn expand_shortcodes(text: &str) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(':') {
+        let after = &rest[start + 1..];
+        match after
+            .find(':')
+            .and_then(|end| Some((end, shortcode(&after[..end])?)))
+        {
+            Some((end, emoji)) => {
+                expanded.push_str(&rest[..start]);
+                expanded.push_str(emoji);
+                rest = &after[end + 1..];
+            }
+            None => {
+                expanded.push_str(&rest[..=start]);
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shortcodes_ignore_case() {
+        assert_eq!(shortcode("tada"), Some("🎉"));
+        assert_eq!(shortcode("TaDa"), Some("🎉"));
+        assert_eq!(shortcode("nope"), None);
+    }
+
+    #[test]
+    fn known_shortcodes_are_expanded_unknown_ones_kept() {
+        assert_eq!(expand_shortcodes("ship it :rocket:"), "ship it 🚀");
+        assert_eq!(expand_shortcodes(":+1::+1:"), "👍👍");
+        assert_eq!(
+            expand_shortcodes("at 10:30 :nope: :tada:"),
+            "at 10:30 :nope: 🎉"
+        );
+        assert_eq!(expand_shortcodes("a single : colon"), "a single : colon");
+        assert_eq!(expand_shortcodes("trailing :"), "trailing :");
+        assert_eq!(expand_shortcodes("ä:heart:ö"), "ä❤️ö");
+    }
+}
@@ -0,0 +1,108 @@
+use ed25519_dalek::{Signer, SigningKey};
+use rand_core::OsRng;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use tuitalk_shared::*;
+use uuid::Uuid;
+
+/// The uuid of this client together with the key that proves it owns it.
+#[derive(Clone)]
+pub struct Identity {
+    pub uuid: Uuid,
+    signing_key: SigningKey,
+}
+
+impl Identity {
+    pub fn load_or_create() -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            uuid: Self::load_or_create_uuid()?,
+            signing_key: Self::load_or_create_key()?,
+        })
+    }
+
+    pub fn authenticate(&self, challenge: &[u8]) -> TalkProtocol {
+        let signature = self.signing_key.sign(&auth_payload(challenge, &self.uuid));
+        TalkProtocol::Authenticate {
+            uuid: self.uuid,
+            public_key: self.signing_key.verifying_key().to_bytes().to_vec(),
+            signature: signature.to_bytes().to_vec(),
+        }
+    }
+
+    /// Login token of the account this client is logged in to.
+    pub fn load_session_token() -> Option<String> {
+        let token = fs::read_to_string(Self::config_dir().ok()?.join("session.token")).ok()?;
+        Some(token.trim().to_string()).filter(|token| !token.is_empty())
+    }
+
+    pub fn save_session_token(token: &str) -> Result<(), Box<dyn std::error::Error>> {
+        write_private(&Self::config_dir()?.join("session.token"), token.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn clear_session_token() -> Result<(), Box<dyn std::error::Error>> {
+        let token_file = Self::config_dir()?.join("session.token");
+        if token_file.exists() {
+            fs::remove_file(token_file)?;
+        }
+        Ok(())
+    }
+
+    fn config_dir() -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let config_dir = dirs::config_dir()
+            .ok_or("Cannot find config directory")?
+            .join("TuiTalk");
+
+        fs::create_dir_all(&config_dir)?;
+        Ok(config_dir)
+    }
+
+    fn load_or_create_uuid() -> Result<Uuid, Box<dyn std::error::Error>> {
+        let config_file = Self::config_dir()?.join("uuid.cfg");
+
+        if config_file.exists() {
+            let content = fs::read_to_string(&config_file)?;
+            if let Ok(uuid) = Uuid::parse_str(content.trim()) {
+                return Ok(uuid);
+            }
+        }
+
+        let new_uuid = Uuid::new_v4();
+        fs::write(&config_file, new_uuid.to_string())?;
+
+        Ok(new_uuid)
+    }
+
+    // The server binds the first key it sees to the uuid, so losing this file means losing the uuid
+    fn load_or_create_key() -> Result<SigningKey, Box<dyn std::error::Error>> {
+        let key_file = Self::config_dir()?.join("identity.key");
+
+        if key_file.exists() {
+            let content = fs::read(&key_file)?;
+            let bytes = <[u8; 32]>::try_from(content.as_slice()).map_err(|_| {
+                format!(
+                    "{} is not a valid key, restore it or remove it together with uuid.cfg to start over",
+                    key_file.display()
+                )
+            })?;
+            return Ok(SigningKey::from_bytes(&bytes));
+        }
+
+        let new_key = SigningKey::generate(&mut OsRng);
+        write_private(&key_file, &new_key.to_bytes())?;
+
+        Ok(new_key)
+    }
+}
+
+// Only the current user may read the file, it holds a secret
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options.open(path)?.write_all(contents)
+}
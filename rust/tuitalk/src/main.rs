@@ -1,13 +1,19 @@
 mod app;
 mod command;
+mod completion;
+mod connection;
+mod emoji;
+mod identity;
+mod rooms;
 mod ui;
 
 use crate::app::App;
+use crate::connection::{ConnectionState, connection_task};
+use crate::identity::Identity;
+use crate::rooms::Rooms;
 use futures_channel::mpsc::unbounded;
 use std::sync::{Arc, Mutex};
-use tokio;
-use tuitalk_shared::TalkProtocol;
-pub use tuitalk_shared::native::{connect, receiver_task, sender_task};
+use tuitalk_shared::{DEFAULT_ROOM, TalkProtocol};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -16,19 +22,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .unwrap_or_else(|| "ws://0.0.0.0:8080".to_string());
 
     let (tx, rx) = unbounded::<TalkProtocol>();
-    let (write, read) = connect(url).await?;
-    let communication: Arc<Mutex<Vec<TalkProtocol>>> = Arc::new(Mutex::new(Vec::new()));
+    let (incoming_tx, incoming_rx) = unbounded::<TalkProtocol>();
+    let communication = Arc::new(Mutex::new(Rooms::new(DEFAULT_ROOM)));
+    let connection = Arc::new(Mutex::new(ConnectionState::Connecting));
+    let identity = Identity::load_or_create()?;
+    let uuid = identity.uuid;
 
-    tokio::spawn(sender_task(rx, write));
-
-    let com = Arc::clone(&communication);
-    tokio::spawn(receiver_task(read, move |msg| {
-        com.lock().unwrap().push(msg);
-    }));
+    tokio::spawn(connection_task(
+        url,
+        rx,
+        incoming_tx,
+        Arc::clone(&communication),
+        Arc::clone(&connection),
+        identity,
+    ));
 
     color_eyre::install()?;
     let terminal = ratatui::init();
-    let app_result = App::new(tx, communication).run(terminal);
+    let app_result = App::new(tx, incoming_rx, communication, connection, uuid).run(terminal);
     ratatui::restore();
     Ok(app_result?)
 }
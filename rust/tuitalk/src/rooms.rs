@@ -0,0 +1,601 @@
+use std::time::{Duration, Instant};
+use tuitalk_shared::validation::room_key;
+use tuitalk_shared::{ReadReceipt, RoomAccess, RoomSummary, TalkMessage, TalkProtocol};
+use uuid::Uuid;
+
+// How long someone counts as typing after their last Typing event
+const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+const EARLIER_DIVIDER: &str = "earlier messages";
+const NEW_DIVIDER: &str = "new messages";
+// Mentions listed by /mentions
+const MAX_MENTIONS: usize = 50;
+
+/// Messages of a single joined room.
+pub struct RoomBuffer {
+    pub room_id: String,
+    pub display_name: String,
+    pub topic: Option<String>,
+    pub access: RoomAccess,
+    /// Whether the server let us in, a refused join closes the tab again.
+    pub joined: bool,
+    pub messages: Vec<TalkProtocol>,
+    pub unread: usize,
+    /// Who announced typing in the room and when.
+    pub typing: Vec<(Uuid, String, Instant)>,
+    /// How far the other members read.
+    pub receipts: Vec<ReadReceipt>,
+    /// Where we stopped reading last time, the divider goes there once the history arrived.
+    unread_from: Option<u64>,
+    /// Newest id we told the server we read.
+    read_sent: u64,
+}
+
+impl RoomBuffer {
+    fn new(name: &str) -> Self {
+        Self {
+            room_id: room_key(name),
+            display_name: name.to_string(),
+            topic: None,
+            access: RoomAccess::default(),
+            joined: false,
+            messages: Vec::new(),
+            unread: 0,
+            typing: Vec::new(),
+            receipts: Vec::new(),
+            unread_from: None,
+            read_sent: 0,
+        }
+    }
+}
+
+/// Every joined room as a tab, frames that belong to no room end up in the active one.
+pub struct Rooms {
+    pub tabs: Vec<RoomBuffer>,
+    pub active: usize,
+}
+
+impl Rooms {
+    pub fn new(name: &str) -> Self {
+        Self {
+            tabs: vec![RoomBuffer::new(name)],
+            active: 0,
+        }
+    }
+
+    pub fn active_room(&self) -> &str {
+        &self.tabs[self.active].room_id
+    }
+
+    pub fn active_tab(&self) -> &RoomBuffer {
+        &self.tabs[self.active]
+    }
+
+    pub fn messages(&self) -> &[TalkProtocol] {
+        &self.tabs[self.active].messages
+    }
+
+    pub fn contains(&self, room_id: &str) -> bool {
+        self.position(room_id).is_some()
+    }
+
+    fn position(&self, room_id: &str) -> Option<usize> {
+        self.tabs.iter().position(|tab| tab.room_id == room_id)
+    }
+
+    /// Adds a local message to the active room.
+    pub fn push(&mut self, msg: TalkProtocol) {
+        self.tabs[self.active].messages.push(msg);
+    }
+
+    pub fn clear(&mut self) {
+        self.tabs[self.active].messages.clear();
+    }
+
+    /// Switches to the room called `name`, opening a new tab for it if needed.
+    pub fn open(&mut self, name: &str) {
+        match self.position(&room_key(name)) {
+            Some(index) => self.select(index),
+            None => {
+                self.tabs.push(RoomBuffer::new(name));
+                self.select(self.tabs.len() - 1);
+            }
+        }
+    }
+
+    /// Closes the room's tab, the last remaining tab is kept.
+    pub fn close(&mut self, room_id: &str) -> bool {
+        let Some(index) = self.position(room_id) else {
+            return false;
+        };
+        if self.tabs.len() == 1 {
+            return false;
+        }
+        self.tabs.remove(index);
+        if self.active >= index && self.active > 0 {
+            self.active -= 1;
+        }
+        self.tabs[self.active].unread = 0;
+        true
+    }
+
+    /// Closes the oldest tab the server has not confirmed yet, joins are answered in order.
+    pub fn refuse(&mut self) -> Option<String> {
+        let room_id = self.tabs.iter().find(|tab| !tab.joined)?.room_id.clone();
+        self.close(&room_id).then_some(room_id)
+    }
+
+    pub fn select(&mut self, index: usize) {
+        if index < self.tabs.len() {
+            self.active = index;
+            self.tabs[index].unread = 0;
+        }
+    }
+
+    pub fn next(&mut self) {
+        self.select((self.active + 1) % self.tabs.len());
+    }
+
+    pub fn previous(&mut self) {
+        self.select((self.active + self.tabs.len() - 1) % self.tabs.len());
+    }
+
+    /// Routes a received frame to the buffer of its room.
+    pub fn receive(&mut self, msg: TalkProtocol) {
+        let index = match msg.room_id() {
+            Some(room_id) => match self.position(room_id) {
+                Some(index) => index,
+                // late events of a room that was already left
+                None => return,
+            },
+            None => self.active,
+        };
+        let tab = &mut self.tabs[index];
+        match &msg {
+            TalkProtocol::RoomInfo { room } => {
+                tab.display_name = room.display_name.clone();
+                tab.topic = room.topic.clone();
+                tab.access = room.access;
+                tab.joined = true;
+                return;
+            }
+            TalkProtocol::TopicChanged { topic, .. } => tab.topic = topic.clone(),
+            TalkProtocol::RoomAccessChanged { access, .. } => tab.access = *access,
+            TalkProtocol::EditMessage {
+                message_id, text, ..
+            } => {
+                if let Some(message) = find_posted(&mut tab.messages, *message_id) {
+                    message.text = text.clone();
+                    message.edited = true;
+                }
+                return;
+            }
+            TalkProtocol::DeleteMessage { message_id, .. } => {
+                if let Some(message) = find_posted(&mut tab.messages, *message_id) {
+                    message.text.clear();
+                    message.reactions.clear();
+                    message.deleted = true;
+                }
+                return;
+            }
+            TalkProtocol::React {
+                message_id,
+                uuid,
+                emoji,
+                ..
+            } => {
+                if let Some(message) = find_posted(&mut tab.messages, *message_id) {
+                    message.react(emoji, *uuid);
+                }
+                return;
+            }
+            TalkProtocol::Unreact {
+                message_id,
+                uuid,
+                emoji,
+                ..
+            } => {
+                if let Some(message) = find_posted(&mut tab.messages, *message_id) {
+                    message.unreact(emoji, *uuid);
+                }
+                return;
+            }
+            TalkProtocol::ReadState {
+                last_read,
+                receipts,
+                ..
+            } => {
+                tab.receipts = receipts.clone();
+                tab.messages.retain(
+                    |proto| !matches!(proto, TalkProtocol::LocalDivider { label } if label == NEW_DIVIDER),
+                );
+                tab.unread_from = *last_read;
+                if let Some(last_read) = last_read {
+                    tab.read_sent = tab.read_sent.max(*last_read);
+                }
+                return;
+            }
+            TalkProtocol::MarkRead {
+                uuid,
+                username,
+                message_id,
+                ..
+            } => {
+                tab.receipts.retain(|receipt| receipt.uuid != *uuid);
+                tab.receipts.push(ReadReceipt {
+                    uuid: *uuid,
+                    username: username.clone(),
+                    message_id: *message_id,
+                });
+                return;
+            }
+            TalkProtocol::History { .. } => {
+                receive_message(&mut tab.messages, msg);
+                if let Some(last_read) = tab.unread_from.take() {
+                    insert_new_divider(&mut tab.messages, last_read);
+                }
+                return;
+            }
+            TalkProtocol::Typing { uuid, username, .. } => {
+                tab.typing.retain(|(typist, _, since)| {
+                    typist != uuid && since.elapsed() < TYPING_TIMEOUT
+                });
+                tab.typing.push((*uuid, username.clone(), Instant::now()));
+                return;
+            }
+            // the message someone was typing arrived
+            TalkProtocol::PostMessage { message } => {
+                tab.typing.retain(|(typist, ..)| *typist != message.uuid);
+                if index != self.active {
+                    tab.unread += 1;
+                }
+            }
+            _ => {}
+        }
+        receive_message(&mut tab.messages, msg);
+    }
+
+    /// Marks the active room as read up to its newest event,
+    /// returns the room and id to tell the server about if that moved.
+    pub fn read_up_to(&mut self) -> Option<(String, u64)> {
+        let tab = &mut self.tabs[self.active];
+        if !tab.joined {
+            return None;
+        }
+        let newest = tab
+            .messages
+            .iter()
+            .rev()
+            .find_map(|proto| proto.message_id())?;
+        if newest <= tab.read_sent {
+            return None;
+        }
+        tab.read_sent = newest;
+        Some((tab.room_id.clone(), newest))
+    }
+
+    /// Names of the members who read the newest message of the active room.
+    pub fn seen_by(&self) -> Vec<String> {
+        let Some(newest) = self.messages().iter().rev().find_map(|proto| match proto {
+            TalkProtocol::PostMessage { message } => Some(message.message_id),
+            _ => None,
+        }) else {
+            return Vec::new();
+        };
+        self.active_tab()
+            .receipts
+            .iter()
+            .filter(|receipt| receipt.message_id >= newest)
+            .map(|receipt| receipt.username.clone())
+            .collect()
+    }
+
+    /// Names of everyone typing in the active room right now.
+    pub fn typing(&self) -> Vec<String> {
+        self.active_tab()
+            .typing
+            .iter()
+            .filter(|(_, _, since)| since.elapsed() < TYPING_TIMEOUT)
+            .map(|(_, username, _)| username.clone())
+            .collect()
+    }
+
+    /// Names seen in the active room, for completing mentions.
+    pub fn usernames(&self) -> Vec<String> {
+        let tab = self.active_tab();
+        let mut usernames: Vec<String> = tab
+            .messages
+            .iter()
+            .flat_map(|proto| match proto {
+                TalkProtocol::PostMessage { message } => vec![message.username.clone()],
+                TalkProtocol::UserJoined { username, .. }
+                | TalkProtocol::UsernameChanged { username, .. } => vec![username.clone()],
+                TalkProtocol::Members { members, .. } => members
+                    .iter()
+                    .map(|member| member.username.clone())
+                    .collect(),
+                _ => Vec::new(),
+            })
+            .chain(tab.typing.iter().map(|(_, username, _)| username.clone()))
+            .chain(tab.receipts.iter().map(|receipt| receipt.username.clone()))
+            .collect();
+        usernames.sort();
+        usernames.dedup();
+        usernames
+    }
+
+    /// A message of the active room by its id.
+    pub fn message(&self, message_id: u64) -> Option<&TalkMessage> {
+        self.messages().iter().find_map(|proto| match proto {
+            TalkProtocol::PostMessage { message } if message.message_id == message_id => {
+                Some(message)
+            }
+            _ => None,
+        })
+    }
+
+    /// The first message of the conversation `message_id` belongs to, as far as it is loaded.
+    pub fn thread_root(&self, message_id: u64) -> u64 {
+        let mut root = message_id;
+        while let Some(parent) = self.message(root).and_then(|message| message.reply_to) {
+            // replies always point to older messages, anything else would loop
+            if parent >= root {
+                break;
+            }
+            root = parent;
+        }
+        root
+    }
+
+    pub fn in_thread(&self, message: &TalkMessage, root: u64) -> bool {
+        self.thread_root(message.message_id) == root
+    }
+
+    /// Moves a message selection in the active room or thread, starting at the newest message.
+    /// Moving past the newest message clears the selection.
+    pub fn step_selection(
+        &self,
+        selected: Option<u64>,
+        older: bool,
+        thread: Option<u64>,
+    ) -> Option<u64> {
+        let ids: Vec<u64> = self
+            .messages()
+            .iter()
+            .filter_map(|proto| match proto {
+                TalkProtocol::PostMessage { message }
+                    if !message.deleted
+                        && thread.is_none_or(|root| self.in_thread(message, root)) =>
+                {
+                    Some(message.message_id)
+                }
+                _ => None,
+            })
+            .collect();
+        let Some(position) = selected.and_then(|id| ids.iter().position(|other| *other == id))
+        else {
+            return if older { ids.last().copied() } else { None };
+        };
+        if older {
+            ids.get(position.saturating_sub(1)).copied()
+        } else {
+            ids.get(position + 1).copied()
+        }
+    }
+
+    /// The newest messages of other users that mention the user, across all tabs, oldest first.
+    pub fn mentions(&self, uuid: Uuid, username: &str) -> Vec<(&str, &TalkMessage)> {
+        let mut mentions: Vec<(&str, &TalkMessage)> = self
+            .tabs
+            .iter()
+            .flat_map(|tab| {
+                tab.messages.iter().filter_map(|proto| match proto {
+                    TalkProtocol::PostMessage { message }
+                        if message.uuid != uuid
+                            && !message.deleted
+                            && message.mentions_user(uuid, username) =>
+                    {
+                        Some((tab.display_name.as_str(), message))
+                    }
+                    _ => None,
+                })
+            })
+            .collect();
+        mentions.sort_by_key(|(_, message)| message.unixtime);
+        let skip = mentions.len().saturating_sub(MAX_MENTIONS);
+        mentions.split_off(skip)
+    }
+
+    /// Id of the newest event shown for the room, used to resume after a reconnect.
+    pub fn last_seen(&self, room_id: &str) -> Option<u64> {
+        let index = self.position(room_id)?;
+        self.tabs[index]
+            .messages
+            .iter()
+            .rev()
+            .find_map(|proto| proto.message_id())
+    }
+}
+
+/// The room list opened by `/rooms`.
+pub struct RoomDirectory {
+    pub rooms: Vec<RoomSummary>,
+    pub selected: usize,
+}
+
+impl RoomDirectory {
+    pub fn new(rooms: Vec<RoomSummary>) -> Self {
+        Self { rooms, selected: 0 }
+    }
+
+    pub fn next(&mut self) {
+        if self.selected + 1 < self.rooms.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn selected_room(&self) -> Option<&RoomSummary> {
+        self.rooms.get(self.selected)
+    }
+}
+
+pub fn receive_message(communication: &mut Vec<TalkProtocol>, msg: TalkProtocol) {
+    match msg {
+        TalkProtocol::History { text, .. } => {
+            let first_shown = communication.iter().find_map(|proto| proto.message_id());
+            // a resumed connection delivers events newer than what is shown,
+            // a join or fetch delivers older ones
+            let (older, newer): (Vec<TalkProtocol>, Vec<TalkProtocol>) = text
+                .into_iter()
+                .partition(|event| match (first_shown, event.message_id()) {
+                    (Some(first_shown), Some(message_id)) => message_id < first_shown,
+                    _ => true,
+                });
+            for event in newer {
+                insert_ordered(communication, event);
+            }
+
+            if older.is_empty() {
+                return;
+            }
+            let has_divider = communication.iter().any(
+                |proto| matches!(proto, TalkProtocol::LocalDivider { label } if label == EARLIER_DIVIDER),
+            );
+            if !has_divider {
+                communication.insert(
+                    0,
+                    TalkProtocol::LocalDivider {
+                        label: EARLIER_DIVIDER.to_string(),
+                    },
+                );
+            }
+            communication.splice(0..0, older);
+        }
+        TalkProtocol::Conversation { username, messages } => {
+            let message = if messages.is_empty() {
+                format!("No direct messages with {} yet", username)
+            } else {
+                format!("Direct messages with {}:", username)
+            };
+            communication.push(TalkProtocol::LocalInformation { message });
+            communication.extend(messages);
+        }
+        _ => insert_ordered(communication, msg),
+    }
+}
+
+// The divider sits in front of the first event newer than what was read
+fn insert_new_divider(communication: &mut Vec<TalkProtocol>, last_read: u64) {
+    let Some(position) = communication
+        .iter()
+        .position(|proto| proto.message_id().is_some_and(|id| id > last_read))
+    else {
+        return;
+    };
+    communication.insert(
+        position,
+        TalkProtocol::LocalDivider {
+            label: NEW_DIVIDER.to_string(),
+        },
+    );
+}
+
+fn find_posted(communication: &mut [TalkProtocol], message_id: u64) -> Option<&mut TalkMessage> {
+    communication.iter_mut().find_map(|proto| match proto {
+        TalkProtocol::PostMessage { message } if message.message_id == message_id => Some(message),
+        _ => None,
+    })
+}
+
+// Room events are kept sorted by their server id and never shown twice
+fn insert_ordered(communication: &mut Vec<TalkProtocol>, msg: TalkProtocol) {
+    let Some(message_id) = msg.message_id() else {
+        communication.push(msg);
+        return;
+    };
+    if communication
+        .iter()
+        .any(|proto| proto.message_id() == Some(message_id))
+    {
+        return;
+    }
+    let position = communication
+        .iter()
+        .position(|proto| proto.message_id().is_some_and(|other| other > message_id))
+        .unwrap_or(communication.len());
+    communication.insert(position, msg);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(message_id: u64) -> TalkProtocol {
+        TalkProtocol::PostMessage {
+            message: TalkMessage {
+                uuid: Uuid::nil(),
+                username: "alice".to_string(),
+                text: format!("message {}", message_id),
+                room_id: "lobby".to_string(),
+                unixtime: message_id,
+                message_id,
+                reply_to: None,
+                edited: false,
+                deleted: false,
+                reactions: Vec::new(),
+                mentions: Vec::new(),
+            },
+        }
+    }
+
+    fn ids(communication: &[TalkProtocol]) -> Vec<Option<u64>> {
+        communication
+            .iter()
+            .map(|proto| proto.message_id())
+            .collect()
+    }
+
+    #[test]
+    fn events_are_kept_in_id_order() {
+        let mut communication = Vec::new();
+        for message_id in [2, 5, 1, 3] {
+            insert_ordered(&mut communication, post(message_id));
+        }
+        assert_eq!(ids(&communication), [Some(1), Some(2), Some(3), Some(5)]);
+    }
+
+    #[test]
+    fn events_seen_twice_are_dropped() {
+        let mut communication = vec![post(1), post(2)];
+        insert_ordered(&mut communication, post(2));
+        assert_eq!(ids(&communication), [Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn local_frames_go_to_the_end() {
+        let mut communication = vec![post(1)];
+        let info = TalkProtocol::LocalInformation {
+            message: "hi".to_string(),
+        };
+        insert_ordered(&mut communication, info);
+        insert_ordered(&mut communication, post(2));
+        assert_eq!(ids(&communication), [Some(1), None, Some(2)]);
+    }
+
+    #[test]
+    fn history_is_merged_around_what_is_shown() {
+        let mut communication = vec![post(3), post(4)];
+        let history = TalkProtocol::History {
+            room_id: "lobby".to_string(),
+            text: vec![post(1), post(2), post(4), post(5)],
+        };
+        receive_message(&mut communication, history);
+        assert_eq!(
+            ids(&communication),
+            [Some(1), Some(2), None, Some(3), Some(4), Some(5)]
+        );
+    }
+}
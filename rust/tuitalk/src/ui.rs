@@ -1,16 +1,22 @@
 use crate::app::{App, InputMode};
-use anyhow::{Context, Result};
+use crate::completion::Completion;
+use crate::connection::ConnectionState;
+use crate::rooms::{RoomBuffer, RoomDirectory, Rooms};
+use anyhow::Result;
 use chrono::{Local, TimeZone, Utc};
 use ratatui::{
     Frame,
-    layout::{Constraint, Layout, Position},
+    layout::{Constraint, Flex, Layout, Position, Rect},
     style::{Color, Modifier, Style, Stylize},
     text::{Line, Span, Text},
-    widgets::{Block, Paragraph, Wrap},
+    widgets::{Block, Clear, List, ListItem, ListState, Paragraph, Tabs, Wrap},
 };
 use tuitalk_shared::*;
 use uuid::Uuid;
 
+// Characters of a replied message shown in the quote above the reply
+const QUOTE_LENGTH: usize = 60;
+
 fn color_from_uuid(uuid: Uuid) -> Color {
     let bytes = uuid.as_bytes();
     let r = bytes[0].saturating_add(64);
@@ -20,30 +26,30 @@ fn color_from_uuid(uuid: Uuid) -> Color {
     Color::Rgb(r, g, b)
 }
 
-fn format_timestamp(unixtime: u64) -> Result<Span<'static>> {
-    let timestamp = Utc
-        .timestamp_opt(unixtime as i64, 0)
-        .single()
-        .context("Invalid Timestamp")?;
-    Ok(Span::raw(format!(
+// Times chrono cannot represent, like a mute far in the future, show as a placeholder
+fn format_timestamp(unixtime: u64) -> Span<'static> {
+    let Some(timestamp) = Utc.timestamp_millis_opt(unixtime as i64).single() else {
+        return Span::raw("<--:--> ");
+    };
+    Span::raw(format!(
         "<{}> ",
         timestamp.with_timezone(&Local).format("%H:%M")
-    )))
+    ))
 }
 
-fn return_server_error(message: &String, code: &String) -> Result<Line<'static>> {
-    let error = Span::styled(format!("Server Error"), Style::default().fg(Color::Red));
-    let code = Span::raw(code.clone());
+fn return_server_error(message: &str, code: &str) -> Result<Line<'static>> {
+    let error = Span::styled("Server Error".to_string(), Style::default().fg(Color::Red));
+    let code = Span::raw(code.to_string());
     let space = Span::raw(": ".to_string());
 
-    let message = Span::raw(message.clone());
+    let message = Span::raw(message.to_string());
 
-    let content = Line::from(vec![error, space, code, message]);
+    let content = Line::from(vec![error, space, code, Span::raw(" "), message]);
     Ok(content)
 }
 
 fn return_local_error(message: &String) -> Result<Line<'_>> {
-    let error = Span::styled(format!("Local Error"), Style::default().fg(Color::Red));
+    let error = Span::styled("Local Error".to_string(), Style::default().fg(Color::Red));
     let space = Span::raw(": ".to_string());
 
     let message = Span::raw(message);
@@ -53,7 +59,7 @@ fn return_local_error(message: &String) -> Result<Line<'_>> {
 }
 
 fn return_local_information(message: &String) -> Result<Line<'_>> {
-    let info = Span::styled(format!("Info"), Style::default().fg(Color::Green));
+    let info = Span::styled("Info".to_string(), Style::default().fg(Color::Green));
     let space = Span::raw(": ".to_string());
 
     let message = Span::raw(message);
@@ -62,21 +68,57 @@ fn return_local_information(message: &String) -> Result<Line<'_>> {
     Ok(content)
 }
 
-fn return_user_left(unixtime: u64, username: &String, uuid: Uuid) -> Result<Line<'_>> {
-    let timestamp = format_timestamp(unixtime)?;
-    let info = Span::styled(format!("Info: "), Style::default().fg(Color::Yellow));
+fn return_divider(label: &str, width: u16) -> Result<Line<'static>> {
+    let label = format!(" {} ", label);
+    let fill = (width as usize).saturating_sub(label.chars().count() + 2) / 2;
+    let rule = "—".repeat(fill);
+    Ok(Line::from(Span::styled(
+        format!("{}{}{}", rule, label, rule),
+        Style::default().fg(Color::DarkGray),
+    )))
+}
+
+fn return_members<'a>(room_id: &str, members: &'a [RoomMember]) -> Result<Line<'a>> {
+    let info = Span::styled("Info".to_string(), Style::default().fg(Color::Green));
+    let header = Span::raw(format!(": {} in room {}: ", members.len(), room_id));
+
+    let mut spans = vec![info, header];
+    for (i, member) in members.iter().enumerate() {
+        if i > 0 {
+            spans.push(Span::raw(", "));
+        }
+        spans.push(Span::styled(
+            member.username.as_str(),
+            Style::default().fg(color_from_uuid(member.uuid)),
+        ));
+    }
+
+    Ok(Line::from(spans))
+}
+
+fn return_user_left<'a>(
+    unixtime: u64,
+    username: &'a String,
+    uuid: Uuid,
+    reason: &Option<String>,
+) -> Result<Line<'a>> {
+    let timestamp = format_timestamp(unixtime);
+    let info = Span::styled("Info: ".to_string(), Style::default().fg(Color::Yellow));
     let username = Span::styled(username, Style::default().fg(color_from_uuid(uuid)));
 
-    let message = Span::raw(" left the room");
+    let message = match reason {
+        Some(reason) => Span::raw(format!(" left the room ({})", reason)),
+        None => Span::raw(" left the room"),
+    };
 
     let content = Line::from(vec![timestamp, info, username, message]);
     Ok(content)
 }
 
 fn return_user_joined(unixtime: u64, username: &String, uuid: Uuid) -> Result<Line<'_>> {
-    let timestamp = format_timestamp(unixtime)?;
+    let timestamp = format_timestamp(unixtime);
 
-    let info = Span::styled(format!("Info: "), Style::default().fg(Color::Yellow));
+    let info = Span::styled("Info: ".to_string(), Style::default().fg(Color::Yellow));
     let username = Span::styled(username, Style::default().fg(color_from_uuid(uuid)));
 
     let message = Span::raw(" joined the room");
@@ -85,45 +127,422 @@ fn return_user_joined(unixtime: u64, username: &String, uuid: Uuid) -> Result<Li
     Ok(content)
 }
 
-fn return_username_changed<'a>(
+fn return_username_changed(
     unixtime: u64,
-    username: &String,
-    old_username: &String,
+    username: &str,
+    old_username: &str,
     uuid: Uuid,
 ) -> Result<Line<'static>> {
-    let timestamp = format_timestamp(unixtime)?;
+    let timestamp = format_timestamp(unixtime);
 
     let info = Span::styled("Info: ".to_string(), Style::default().fg(Color::Yellow));
-    let old_username = Span::styled(old_username.clone(), Style::default().fg(color_from_uuid(uuid)));
+    let old_username = Span::styled(
+        old_username.to_string(),
+        Style::default().fg(color_from_uuid(uuid)),
+    );
 
     let message = Span::raw(" changed his name to ");
-    let username = Span::styled(username.clone(), Style::default().fg(color_from_uuid(uuid)));
+    let username = Span::styled(
+        username.to_string(),
+        Style::default().fg(color_from_uuid(uuid)),
+    );
 
     let content = Line::from(vec![timestamp, info, old_username, message, username]);
     Ok(content)
 }
 
-fn return_posted_message(message: &TalkMessage) -> Result<Line<'_>> {
-    let timestamp = format_timestamp(message.unixtime)?;
+fn return_topic_changed(
+    unixtime: u64,
+    username: &str,
+    uuid: Uuid,
+    topic: &Option<String>,
+) -> Result<Line<'static>> {
+    let timestamp = format_timestamp(unixtime);
+
+    let info = Span::styled("Info: ".to_string(), Style::default().fg(Color::Yellow));
+    let username = Span::styled(
+        username.to_string(),
+        Style::default().fg(color_from_uuid(uuid)),
+    );
+
+    let message = match topic {
+        Some(topic) => Span::raw(format!(" changed the topic to: {}", topic)),
+        None => Span::raw(" removed the topic"),
+    };
+
+    let content = Line::from(vec![timestamp, info, username, message]);
+    Ok(content)
+}
+
+fn return_access_changed(
+    unixtime: u64,
+    username: &str,
+    uuid: Uuid,
+    access: RoomAccess,
+) -> Result<Line<'static>> {
+    let timestamp = format_timestamp(unixtime);
+
+    let info = Span::styled("Info: ".to_string(), Style::default().fg(Color::Yellow));
+    let username = Span::styled(
+        username.to_string(),
+        Style::default().fg(color_from_uuid(uuid)),
+    );
+
+    let message = match access {
+        RoomAccess::Public => Span::raw(" opened the room to everyone"),
+        RoomAccess::Password => Span::raw(" protected the room with a password"),
+        RoomAccess::InviteOnly => Span::raw(" made the room invite only"),
+    };
+
+    let content = Line::from(vec![timestamp, info, username, message]);
+    Ok(content)
+}
+
+fn return_moderated(
+    unixtime: u64,
+    username: &str,
+    uuid: Uuid,
+    target: &str,
+    target_uuid: Uuid,
+    action: &ModerationAction,
+    reason: &Option<String>,
+) -> Result<Line<'static>> {
+    let timestamp = format_timestamp(unixtime);
+
+    let moderation = Span::styled("Moderation: ".to_string(), Style::default().fg(Color::Red));
+    let username = Span::styled(
+        username.to_string(),
+        Style::default().fg(color_from_uuid(uuid)),
+    );
+    let target = Span::styled(
+        target.to_string(),
+        Style::default().fg(color_from_uuid(target_uuid)),
+    );
+
+    let (verb, rest) = match action {
+        ModerationAction::Kicked => (" kicked ", String::new()),
+        ModerationAction::Banned => (" banned ", String::new()),
+        ModerationAction::Unbanned => (" unbanned ", String::new()),
+        ModerationAction::Muted { until } if *until <= unixtime => (" unmuted ", String::new()),
+        ModerationAction::Muted { until } => {
+            let until = format_timestamp(*until);
+            (" muted ", format!(" until {}", until.content.trim()))
+        }
+        ModerationAction::RoleChanged { role } => {
+            let role = match role {
+                RoomRole::Owner => "owner",
+                RoomRole::Moderator => "a moderator",
+                RoomRole::Member => "a member",
+            };
+            (" made ", format!(" {}", role))
+        }
+    };
+    let reason = match reason {
+        Some(reason) => format!(" ({})", reason),
+        None => String::new(),
+    };
+
+    let content = Line::from(vec![
+        timestamp,
+        moderation,
+        username,
+        Span::raw(verb),
+        target,
+        Span::raw(format!("{}{}", rest, reason)),
+    ]);
+    Ok(content)
+}
+
+fn return_invited(display_name: &str, username: &str, invitee: &str) -> Result<Line<'static>> {
+    let info = Span::styled("Info".to_string(), Style::default().fg(Color::Green));
+    let message = Span::raw(format!(
+        ": {} invited {} to {}, join with /room {}",
+        username, invitee, display_name, display_name
+    ));
+
+    Ok(Line::from(vec![info, message]))
+}
+
+// Marks rooms that not everyone can join
+fn access_marker(access: RoomAccess) -> &'static str {
+    match access {
+        RoomAccess::Public => "",
+        RoomAccess::Password => " [locked]",
+        RoomAccess::InviteOnly => " [invite only]",
+    }
+}
+
+// Messages that mention the user stand out in yellow
+fn return_posted_message(message: &TalkMessage, mentioned: bool) -> Result<Line<'_>> {
+    let timestamp = format_timestamp(message.unixtime);
 
     let username = Span::styled(
         format!("{}: ", message.username),
         Style::default().fg(color_from_uuid(message.uuid)),
     );
 
-    let message = Span::raw(message.text.clone());
+    if message.deleted {
+        let tombstone = Span::styled(
+            "message deleted",
+            Style::default().fg(Color::DarkGray).italic(),
+        );
+        return Ok(Line::from(vec![timestamp, username, tombstone]));
+    }
+    let text = if mentioned {
+        Span::styled(
+            message.text.clone(),
+            Style::default().fg(Color::Yellow).bold(),
+        )
+    } else {
+        Span::raw(message.text.clone())
+    };
+    let mut spans = vec![timestamp, username, text];
+    if message.edited {
+        spans.push(Span::styled(
+            " (edited)",
+            Style::default().fg(Color::DarkGray),
+        ));
+    }
+
+    let content = Line::from(spans);
+    Ok(content)
+}
+
+// A compact row under a message, the user's own reactions stand out
+fn return_reactions(reactions: &[Reaction], own: Uuid) -> Result<Line<'static>> {
+    let mut spans = vec![Span::raw("    ")];
+    for reaction in reactions {
+        let style = if reaction.users.contains(&own) {
+            Style::default().fg(Color::Yellow)
+        } else {
+            Style::default().fg(Color::DarkGray)
+        };
+        spans.push(Span::styled(
+            format!("{} {}  ", reaction.emoji, reaction.users.len()),
+            style,
+        ));
+    }
+    Ok(Line::from(spans))
+}
+
+// Shown above a reply, the replied message may be older than what is loaded
+fn return_quote(replied: Option<&TalkMessage>) -> Result<Line<'static>> {
+    let style = Style::default().fg(Color::DarkGray);
+    let Some(replied) = replied else {
+        return Ok(Line::from(Span::styled(
+            "  ┌ reply to an earlier message",
+            style,
+        )));
+    };
+    let text = if replied.deleted {
+        "message deleted".to_string()
+    } else if replied.text.chars().count() > QUOTE_LENGTH {
+        format!(
+            "{}…",
+            replied.text.chars().take(QUOTE_LENGTH).collect::<String>()
+        )
+    } else {
+        replied.text.clone()
+    };
+
+    let content = Line::from(vec![
+        Span::styled("  ┌ ", style),
+        Span::styled(
+            format!("{}: ", replied.username),
+            Style::default().fg(color_from_uuid(replied.uuid)),
+        ),
+        Span::styled(text, style.italic()),
+    ]);
+    Ok(content)
+}
 
-    let content = Line::from(vec![timestamp, username, message]);
+fn return_direct_message<'a>(
+    unixtime: u64,
+    uuid: Uuid,
+    username: &'a str,
+    recipient_uuid: Uuid,
+    recipient: &'a str,
+    text: &'a str,
+) -> Result<Line<'a>> {
+    let timestamp = format_timestamp(unixtime);
+    let direct = Span::styled("DM ", Style::default().fg(Color::Magenta).bold());
+    let username = Span::styled(username, Style::default().fg(color_from_uuid(uuid)));
+    let arrow = Span::raw(" → ");
+    let recipient = Span::styled(
+        recipient,
+        Style::default().fg(color_from_uuid(recipient_uuid)),
+    );
+    let text = Span::styled(
+        format!(": {}", text),
+        Style::default().fg(Color::Magenta).italic(),
+    );
+
+    let content = Line::from(vec![timestamp, direct, username, arrow, recipient, text]);
     Ok(content)
 }
 
+fn return_room_tabs(rooms: &Rooms) -> Tabs<'static> {
+    let titles = rooms.tabs.iter().map(|tab| {
+        let mut spans = vec![Span::raw(format!(
+            "{}{}",
+            tab.display_name,
+            access_marker(tab.access)
+        ))];
+        if tab.unread > 0 {
+            spans.push(Span::styled(
+                format!(" ({})", tab.unread),
+                Style::default().fg(Color::Yellow).bold(),
+            ));
+        }
+        Line::from(spans)
+    });
+    Tabs::new(titles)
+        .select(rooms.active)
+        .highlight_style(Style::default().fg(Color::Black).bg(Color::Yellow))
+}
+
+fn return_room_title(tab: &RoomBuffer, thread: bool) -> Line<'static> {
+    let mut spans = vec![Span::raw(format!(
+        " Chatting in {}{} ",
+        tab.display_name,
+        access_marker(tab.access)
+    ))];
+    if let Some(topic) = &tab.topic {
+        spans.push(Span::styled(
+            format!("— {} ", topic),
+            Style::default().fg(Color::Cyan),
+        ));
+    }
+    if thread {
+        spans.push(Span::styled(
+            "[thread, Esc to go back] ",
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+    Line::from(spans)
+}
+
+fn return_room_summary(room: &RoomSummary) -> ListItem<'static> {
+    let mut spans = vec![
+        Span::raw(room.display_name.clone()).bold(),
+        Span::styled(access_marker(room.access), Style::default().fg(Color::Red)),
+        Span::styled(
+            format!("  {} online", room.members),
+            Style::default().fg(Color::Green),
+        ),
+    ];
+    let last_activity = format_timestamp(room.last_activity);
+    spans.push(Span::styled(
+        format!("  last active {}", last_activity.content.trim()),
+        Style::default().fg(Color::DarkGray),
+    ));
+    if let Some(topic) = &room.topic {
+        spans.push(Span::styled(
+            format!("  {}", topic),
+            Style::default().fg(Color::Cyan),
+        ));
+    }
+    ListItem::new(Line::from(spans))
+}
+
+fn popup_area(area: Rect, percent_x: u16, percent_y: u16) -> Rect {
+    let [area] = Layout::vertical([Constraint::Percentage(percent_y)])
+        .flex(Flex::Center)
+        .areas(area);
+    let [area] = Layout::horizontal([Constraint::Percentage(percent_x)])
+        .flex(Flex::Center)
+        .areas(area);
+    area
+}
+
+fn draw_directory(directory: &RoomDirectory, frame: &mut Frame) {
+    let area = popup_area(frame.area(), 70, 60);
+    let block = Block::bordered().title(" Rooms — Enter to join, Esc to close ");
+    frame.render_widget(Clear, area);
+
+    if directory.rooms.is_empty() {
+        let empty = Paragraph::new("No active rooms, create one with /room {name}").block(block);
+        frame.render_widget(empty, area);
+        return;
+    }
+
+    let list = List::new(directory.rooms.iter().map(return_room_summary))
+        .block(block)
+        .highlight_style(Style::default().fg(Color::Black).bg(Color::Yellow))
+        .highlight_symbol("> ");
+    let mut state = ListState::default().with_selected(Some(directory.selected));
+    frame.render_stateful_widget(list, area, &mut state);
+}
+
+// Rows of the completion popup before its list scrolls
+const COMPLETION_ROWS: usize = 6;
+
+// The matches for Tab start under the completed word, above the input when there is room
+fn draw_completion(completion: &Completion, input: &str, input_area: Rect, frame: &mut Frame) {
+    let height = completion.candidates.len().min(COMPLETION_ROWS) as u16 + 2;
+    let width = completion
+        .candidates
+        .iter()
+        .map(|candidate| candidate.chars().count())
+        .max()
+        .unwrap_or_default() as u16
+        + 4;
+    let x = input_area.x + 1 + input[..completion.start].chars().count() as u16;
+    let y = if input_area.y >= height {
+        input_area.y - height
+    } else {
+        input_area.bottom()
+    };
+    let area = Rect::new(x, y, width, height).intersection(frame.area());
+
+    let list = List::new(completion.candidates.iter().map(String::as_str))
+        .block(Block::bordered())
+        .highlight_style(Style::default().fg(Color::Black).bg(Color::Yellow));
+    let mut state = ListState::default().with_selected(Some(completion.selected));
+    frame.render_widget(Clear, area);
+    frame.render_stateful_widget(list, area, &mut state);
+}
+
+fn return_seen_by(seen_by: &[String]) -> Line<'static> {
+    Line::from(Span::styled(
+        format!("    seen by {}", seen_by.join(", ")),
+        Style::default().fg(Color::DarkGray).italic(),
+    ))
+}
+
+fn return_typing(typing: &[String]) -> Line<'static> {
+    let text = match typing {
+        [one] => format!("{} is typing…", one),
+        [one, two] => format!("{} and {} are typing…", one, two),
+        _ => "Several people are typing…".to_string(),
+    };
+    Line::from(Span::styled(
+        text,
+        Style::default().fg(Color::DarkGray).italic(),
+    ))
+}
+
+fn return_connection_state(state: &ConnectionState) -> Line<'static> {
+    let (text, color) = match state {
+        ConnectionState::Connecting => ("connecting…".to_string(), Color::Yellow),
+        ConnectionState::Handshaking => ("handshaking…".to_string(), Color::Yellow),
+        ConnectionState::Connected { .. } => ("● connected".to_string(), Color::Green),
+        ConnectionState::Rejected { .. } => ("rejected by server".to_string(), Color::Red),
+        ConnectionState::Reconnecting { attempt } => {
+            (format!("offline, retry #{}", attempt), Color::Red)
+        }
+    };
+    Line::from(Span::styled(text, Style::default().fg(color))).right_aligned()
+}
+
 pub fn draw(app: &mut App, frame: &mut Frame) {
     let vertical = Layout::vertical([
         Constraint::Length(1),
         Constraint::Length(3),
+        Constraint::Length(1),
         Constraint::Min(1),
     ]);
-    let [help_area, input_area, messages_area] = vertical.areas(frame.area());
+    let [help_area, input_area, tabs_area, messages_area] = vertical.areas(frame.area());
 
     let (msg, style) = match app.input_mode {
         InputMode::Normal => (
@@ -132,7 +551,21 @@ pub fn draw(app: &mut App, frame: &mut Frame) {
                 "q".bold(),
                 " to exit, ".into(),
                 "i".bold(),
-                " to start editing.".bold(),
+                " to start editing, ".bold(),
+                "Tab".bold(),
+                " to switch rooms, ".bold(),
+                "↑/↓".bold(),
+                " to select a message, ".into(),
+                "e".bold(),
+                "/".into(),
+                "x".bold(),
+                " to edit/delete it, ".into(),
+                "r".bold(),
+                " to reply, ".into(),
+                "t".bold(),
+                " for its thread, ".into(),
+                "+".bold(),
+                " to react.".into(),
             ],
             Style::default().add_modifier(Modifier::RAPID_BLINK),
         ),
@@ -142,20 +575,52 @@ pub fn draw(app: &mut App, frame: &mut Frame) {
                 "Esc".bold(),
                 " to stop editing, ".into(),
                 "Enter".bold(),
-                " to send".into(),
+                " to send, ".into(),
+                "Tab".bold(),
+                " to complete".into(),
             ],
             Style::default(),
         ),
     };
-    let text = Text::from(Line::from(msg)).patch_style(style);
+    let [help_area, state_area] =
+        Layout::horizontal([Constraint::Min(1), Constraint::Length(20)]).areas(help_area);
+    // someone typing in the room is more interesting than the key hints
+    let typing = app
+        .communication
+        .lock()
+        .expect("Communication Vector")
+        .typing();
+    let text = if typing.is_empty() {
+        Text::from(Line::from(msg)).patch_style(style)
+    } else {
+        Text::from(return_typing(&typing))
+    };
     frame.render_widget(Paragraph::new(text), help_area);
 
+    let state = return_connection_state(&app.connection.lock().expect("Connection state"));
+    frame.render_widget(Paragraph::new(state), state_area);
+
+    let input_title = if app.editing.is_some() {
+        "Edit message".to_string()
+    } else if app.reacting.is_some() {
+        "React with an emoji or :shortcode:, the same one again takes it back".to_string()
+    } else if let Some(replied) = app.replying.and_then(|id| {
+        app.communication
+            .lock()
+            .expect("Communication Vector")
+            .message(id)
+            .cloned()
+    }) {
+        format!("Reply to {}", replied.username)
+    } else {
+        "Input".to_string()
+    };
     let input = Paragraph::new(app.input.as_str())
         .style(match app.input_mode {
             InputMode::Normal => Style::default(),
             InputMode::Editing => Style::default().fg(Color::Yellow),
         })
-        .block(Block::bordered().title("Input"));
+        .block(Block::bordered().title(input_title));
     frame.render_widget(input, input_area);
 
     if let InputMode::Editing = app.input_mode {
@@ -165,36 +630,138 @@ pub fn draw(app: &mut App, frame: &mut Frame) {
         ));
     }
 
-    let full_messages = app.communication.lock().expect("Vector with all messages");
-
-    let lines: Vec<Line> = full_messages
+    let selected = app.selected;
+    let thread = app.thread;
+    let own = app.uuid;
+    let own_username = app.username.clone();
+    let mentioned =
+        |message: &TalkMessage| message.uuid != own && message.mentions_user(own, &own_username);
+    let rooms = app.communication.lock().expect("Vector with all messages");
+    frame.render_widget(return_room_tabs(&rooms), tabs_area);
+
+    let mut lines: Vec<Line> = rooms
+        .messages()
         .iter()
-        .map(|proto| match proto {
-            TalkProtocol::Error { code, message } => return_server_error(message, code),
-            TalkProtocol::LocalError { message } => return_local_error(message),
-            TalkProtocol::LocalInformation { message } => return_local_information(message),
-            TalkProtocol::PostMessage { message } => return_posted_message(message),
-            TalkProtocol::UserJoined {
-                uuid,
-                username,
-                room_id: _,
-                unixtime,
-            } => return_user_joined(*unixtime, username, *uuid),
-            TalkProtocol::UserLeft {
-                uuid,
-                username,
-                room_id: _,
-                unixtime,
-            } => return_user_left(*unixtime, username, *uuid),
-            TalkProtocol::UsernameChanged {
-                uuid,
-                username,
-                old_username,
-                unixtime,
-            } => return_username_changed(*unixtime, username, old_username, *uuid),
-            _ => Ok(Line::from(Span::raw(format!("{:?}", proto)))),
+        .filter(|proto| match (thread, proto) {
+            (Some(root), TalkProtocol::PostMessage { message }) => rooms.in_thread(message, root),
+            (Some(_), _) => false,
+            (None, _) => true,
+        })
+        .flat_map(|proto| {
+            let (quote, reactions) = match proto {
+                TalkProtocol::PostMessage { message } => (
+                    message
+                        .reply_to
+                        .map(|reply_to| return_quote(rooms.message(reply_to))),
+                    Some(&message.reactions)
+                        .filter(|reactions| !reactions.is_empty())
+                        .map(|reactions| return_reactions(reactions, own)),
+                ),
+                _ => (None, None),
+            };
+            let line = match proto {
+                TalkProtocol::Error { code, message } => return_server_error(message, code),
+                TalkProtocol::LocalError { message } => return_local_error(message),
+                TalkProtocol::LocalInformation { message } => return_local_information(message),
+                TalkProtocol::PostMessage { message } if Some(message.message_id) == selected => {
+                    return_posted_message(message, mentioned(message))
+                        .map(|line| line.style(Style::default().bg(Color::DarkGray)))
+                }
+                TalkProtocol::PostMessage { message } => {
+                    return_posted_message(message, mentioned(message))
+                }
+                TalkProtocol::UserJoined {
+                    uuid,
+                    username,
+                    room_id: _,
+                    unixtime,
+                    ..
+                } => return_user_joined(*unixtime, username, *uuid),
+                TalkProtocol::UserLeft {
+                    uuid,
+                    username,
+                    room_id: _,
+                    unixtime,
+                    reason,
+                    ..
+                } => return_user_left(*unixtime, username, *uuid, reason),
+                TalkProtocol::UsernameChanged {
+                    uuid,
+                    username,
+                    old_username,
+                    unixtime,
+                    ..
+                } => return_username_changed(*unixtime, username, old_username, *uuid),
+                TalkProtocol::LocalDivider { label } => return_divider(label, messages_area.width),
+                TalkProtocol::Members { room_id, members } => return_members(room_id, members),
+                TalkProtocol::TopicChanged {
+                    uuid,
+                    username,
+                    topic,
+                    unixtime,
+                    ..
+                } => return_topic_changed(*unixtime, username, *uuid, topic),
+                TalkProtocol::RoomAccessChanged {
+                    uuid,
+                    username,
+                    access,
+                    unixtime,
+                    ..
+                } => return_access_changed(*unixtime, username, *uuid, *access),
+                TalkProtocol::Moderated {
+                    uuid,
+                    username,
+                    target_uuid,
+                    target,
+                    action,
+                    reason,
+                    unixtime,
+                    ..
+                } => return_moderated(
+                    *unixtime,
+                    username,
+                    *uuid,
+                    target,
+                    *target_uuid,
+                    action,
+                    reason,
+                ),
+                TalkProtocol::Invited {
+                    display_name,
+                    username,
+                    invitee,
+                    ..
+                } => return_invited(display_name, username, invitee),
+                TalkProtocol::DirectMessage {
+                    uuid,
+                    username,
+                    recipient_uuid,
+                    recipient,
+                    text,
+                    unixtime,
+                    ..
+                } => return_direct_message(
+                    *unixtime,
+                    *uuid,
+                    username,
+                    *recipient_uuid,
+                    recipient,
+                    text,
+                ),
+                _ => Ok(Line::from(Span::raw(format!("{:?}", proto)))),
+            };
+            quote
+                .into_iter()
+                .chain(std::iter::once(line))
+                .chain(reactions)
         })
         .collect::<Result<Vec<Line>, anyhow::Error>>().expect("lines of text");
+    if app.show_seen && thread.is_none() {
+        let seen_by = rooms.seen_by();
+        if !seen_by.is_empty() {
+            lines.push(return_seen_by(&seen_by));
+        }
+    }
     let paragraph = Paragraph::new(lines).wrap(Wrap { trim: true });
 
     let total_lines = paragraph.line_count(messages_area.width);
@@ -208,11 +775,19 @@ pub fn draw(app: &mut App, frame: &mut Frame) {
     app.scroll = app
         .scroll
         .clamp(0, total_lines.saturating_sub(visible_height));
+    app.at_bottom = app.scroll >= app.max_scroll;
 
     frame.render_widget(
         paragraph
-            .block(Block::bordered().title(format!(" Chatting in Room {} ", app.room)))
+            .block(Block::bordered().title(return_room_title(rooms.active_tab(), thread.is_some())))
             .scroll((app.scroll as u16, 0)),
         messages_area,
     );
+
+    if let Some(completion) = &app.completion {
+        draw_completion(completion, &app.input, input_area, frame);
+    }
+    if let Some(directory) = &app.directory {
+        draw_directory(directory, frame);
+    }
 }
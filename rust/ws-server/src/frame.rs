@@ -0,0 +1,62 @@
+use tokio_tungstenite::tungstenite::protocol::{Message, frame::coding::CloseCode};
+use tuitalk_shared::validation::MAX_FRAME_SIZE;
+use tuitalk_shared::{TalkProtocol, error_code};
+
+/// What the server makes of a single WebSocket message.
+pub enum Frame {
+    Protocol(TalkProtocol),
+    /// Ping, Pong and raw frames, tungstenite already answers pings
+    Control,
+    Close,
+    Invalid(FrameError),
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    Oversized(usize),
+    Undecodable(String),
+    Text,
+}
+
+impl FrameError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            FrameError::Oversized(_) => error_code::FRAME_TOO_LARGE,
+            FrameError::Undecodable(_) | FrameError::Text => error_code::INVALID_FRAME,
+        }
+    }
+
+    /// Close code used once the client sent too many of these.
+    pub fn close_code(&self) -> CloseCode {
+        match self {
+            FrameError::Oversized(_) => CloseCode::Size,
+            FrameError::Undecodable(_) => CloseCode::Invalid,
+            FrameError::Text => CloseCode::Unsupported,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            FrameError::Oversized(size) => {
+                format!("frame of {} bytes exceeds {} bytes", size, MAX_FRAME_SIZE)
+            }
+            FrameError::Undecodable(error) => format!("frame could not be decoded: {}", error),
+            FrameError::Text => "text frames are not supported, send binary".to_string(),
+        }
+    }
+}
+
+pub fn decode(msg: Message) -> Frame {
+    match msg {
+        Message::Binary(bin) if bin.len() > MAX_FRAME_SIZE => {
+            Frame::Invalid(FrameError::Oversized(bin.len()))
+        }
+        Message::Binary(bin) => match TalkProtocol::deserialize(&bin) {
+            Ok(msg) => Frame::Protocol(msg),
+            Err(e) => Frame::Invalid(FrameError::Undecodable(e.to_string())),
+        },
+        Message::Text(_) => Frame::Invalid(FrameError::Text),
+        Message::Close(_) => Frame::Close,
+        Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => Frame::Control,
+    }
+}
@@ -1,5 +1,7 @@
-mod wsserver;
+mod frame;
+mod ratelimit;
 mod redis;
+mod wsserver;
 
 use dotenvy::dotenv;
 use anyhow::Result;
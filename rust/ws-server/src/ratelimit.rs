@@ -0,0 +1,128 @@
+use std::env;
+use std::time::{Duration, Instant};
+
+// tuitalk paces its frames to stay below these defaults
+const DEFAULT_BURST: f64 = 10.0;
+const DEFAULT_PER_SECOND: f64 = 2.0;
+const DEFAULT_MAX_STRIKES: u32 = 5;
+// Strikes are forgotten after this long without another violation
+const STRIKE_RESET: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimitConfig {
+    pub burst: f64,
+    pub per_second: f64,
+    pub max_strikes: u32,
+}
+
+impl RateLimitConfig {
+    pub fn from_env() -> Self {
+        Self {
+            burst: env_or("RATE_LIMIT_BURST", DEFAULT_BURST),
+            per_second: env_or("RATE_LIMIT_PER_SECOND", DEFAULT_PER_SECOND),
+            max_strikes: env_or("RATE_LIMIT_MAX_STRIKES", DEFAULT_MAX_STRIKES),
+        }
+    }
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Token bucket for a single connection, the per-uuid bucket lives in redis.
+#[derive(Debug)]
+pub struct RateLimiter {
+    pub config: RateLimitConfig,
+    tokens: f64,
+    last_refill: Instant,
+    strikes: u32,
+    last_strike: Instant,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(RateLimitConfig::from_env())
+    }
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            tokens: config.burst,
+            last_refill: Instant::now(),
+            strikes: 0,
+            last_strike: Instant::now(),
+        }
+    }
+
+    pub fn try_take(&mut self) -> bool {
+        let elapsed = self.last_refill.elapsed().as_secs_f64();
+        self.last_refill = Instant::now();
+        self.tokens = (self.tokens + elapsed * self.config.per_second).min(self.config.burst);
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// Records a violation, true once the client exceeded the limit too often.
+    pub fn strike(&mut self) -> bool {
+        if self.last_strike.elapsed() > STRIKE_RESET {
+            self.strikes = 0;
+        }
+        self.last_strike = Instant::now();
+        self.strikes += 1;
+        self.strikes >= self.config.max_strikes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(burst: f64, per_second: f64, max_strikes: u32) -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            burst,
+            per_second,
+            max_strikes,
+        })
+    }
+
+    #[test]
+    fn burst_is_allowed_then_throttled() {
+        let mut limiter = limiter(3.0, 1.0, 5);
+        assert!((0..3).all(|_| limiter.try_take()));
+        assert!(!limiter.try_take());
+    }
+
+    #[test]
+    fn tokens_refill_over_time_up_to_the_burst() {
+        let mut limiter = limiter(3.0, 2.0, 5);
+        while limiter.try_take() {}
+        limiter.last_refill -= Duration::from_secs(1);
+        assert!(limiter.try_take());
+        assert!(limiter.try_take());
+        assert!(!limiter.try_take());
+
+        limiter.last_refill -= Duration::from_secs(60);
+        assert!((0..3).all(|_| limiter.try_take()));
+        assert!(!limiter.try_take());
+    }
+
+    #[test]
+    fn strikes_disconnect_unless_they_are_old() {
+        let mut limiter = limiter(1.0, 1.0, 3);
+        assert!(!limiter.strike());
+        assert!(!limiter.strike());
+        limiter.last_strike -= STRIKE_RESET + Duration::from_secs(1);
+        assert!(!limiter.strike());
+        assert!(!limiter.strike());
+        assert!(limiter.strike());
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::{Result, anyhow};
+use argon2::Argon2;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use rand_core::{OsRng, RngCore};
+use redis::{Commands, Connection};
+use uuid::Uuid;
+
+// Login tokens stay valid for 30 days
+const TOKEN_TTL_SECONDS: u64 = 60 * 60 * 24 * 30;
+const TOKEN_LENGTH: usize = 32;
+
+pub struct Account {
+    pub username: String,
+    pub uuid: Uuid,
+}
+
+// Accounts are looked up case-insensitively so "Alice" also reserves "alice"
+fn account_key(username: &str) -> String {
+    format!("account:{}", username.to_lowercase())
+}
+
+fn token_key(token: &str) -> String {
+    format!("token:{}", token)
+}
+
+fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow!("hashing password: {}", e))?
+        .to_string())
+}
+
+fn verify_password(password: &str, password_hash: &str) -> Result<bool> {
+    let parsed = PasswordHash::new(password_hash).map_err(|e| anyhow!("stored hash: {}", e))?;
+    Ok(Argon2::default()
+        .verify_password(password.as_bytes(), &parsed)
+        .is_ok())
+}
+
+/// `hash_password` on the blocking pool, argon2 is slow on purpose and would stall the executor.
+pub async fn hash_password_blocking(password: &str) -> Result<String> {
+    let password = password.to_string();
+    tokio::task::spawn_blocking(move || hash_password(&password)).await?
+}
+
+/// `verify_password` on the blocking pool.
+pub async fn verify_password_blocking(password: &str, password_hash: &str) -> Result<bool> {
+    let (password, password_hash) = (password.to_string(), password_hash.to_string());
+    tokio::task::spawn_blocking(move || verify_password(&password, &password_hash)).await?
+}
+
+/// Creates the account unless the name is taken, the uuid becomes the account's identity.
+/// The password is hashed beforehand so the redis connection isn't held up by it.
+pub fn register(
+    conn: &mut Connection,
+    username: &str,
+    password_hash: &str,
+    uuid: &Uuid,
+) -> Result<Option<Account>> {
+    let key = account_key(username);
+    let created: bool = conn.hset_nx(&key, "uuid", uuid.to_string())?;
+    if !created {
+        return Ok(None);
+    }
+
+    let _: () = conn.hset_multiple(
+        &key,
+        &[("username", username), ("password_hash", password_hash)],
+    )?;
+
+    Ok(Some(Account {
+        username: username.to_string(),
+        uuid: *uuid,
+    }))
+}
+
+/// The account called `username` with its password hash, checking the password is up to the caller.
+pub fn find_account(conn: &mut Connection, username: &str) -> Result<Option<(Account, String)>> {
+    let (stored_name, uuid, password_hash): (Option<String>, Option<String>, Option<String>) = conn
+        .hget(
+            account_key(username),
+            &["username", "uuid", "password_hash"],
+        )?;
+    let (Some(username), Some(uuid), Some(password_hash)) = (stored_name, uuid, password_hash)
+    else {
+        return Ok(None);
+    };
+
+    let account = Account {
+        username,
+        uuid: Uuid::parse_str(&uuid)?,
+    };
+    Ok(Some((account, password_hash)))
+}
+
+pub fn create_token(conn: &mut Connection, account: &Account) -> Result<String> {
+    let mut bytes = [0u8; TOKEN_LENGTH];
+    OsRng.fill_bytes(&mut bytes);
+    let token: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+    let _: () = conn.set_ex(token_key(&token), &account.username, TOKEN_TTL_SECONDS)?;
+    Ok(token)
+}
+
+pub fn resolve_token(conn: &mut Connection, token: &str) -> Result<Option<Account>> {
+    let username: Option<String> = conn.get(token_key(token))?;
+    let Some(username) = username else {
+        return Ok(None);
+    };
+
+    let (stored_name, uuid): (Option<String>, Option<String>) =
+        conn.hget(account_key(&username), &["username", "uuid"])?;
+    match (stored_name, uuid) {
+        (Some(username), Some(uuid)) => Ok(Some(Account {
+            username,
+            uuid: Uuid::parse_str(&uuid)?,
+        })),
+        _ => Ok(None),
+    }
+}
+
+/// The account that owns `username`, if any.
+pub fn owner_of(conn: &mut Connection, username: &str) -> Result<Option<Uuid>> {
+    let uuid: Option<String> = conn.hget(account_key(username), "uuid")?;
+    Ok(uuid.and_then(|uuid| Uuid::parse_str(&uuid).ok()))
+}
@@ -0,0 +1,66 @@
+use crate::redis::history::MAX_FETCH_LIMIT;
+use anyhow::Result;
+use redis::{Commands, Connection};
+use tuitalk_shared::TalkProtocol;
+use uuid::Uuid;
+
+// Both participants share one conversation regardless of who wrote first
+fn conversation_id(a: &Uuid, b: &Uuid) -> String {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    format!("{}:{}", first, second)
+}
+
+fn conversation_key(a: &Uuid, b: &Uuid) -> String {
+    format!("direct:{}", conversation_id(a, b))
+}
+
+fn direct_id_key(a: &Uuid, b: &Uuid) -> String {
+    format!("message_id:direct:{}", conversation_id(a, b))
+}
+
+/// Channel every connection of `uuid` listens on for direct messages.
+pub fn user_channel(uuid: &Uuid) -> String {
+    format!("user:{}", uuid)
+}
+
+/// Next id of the conversation between `a` and `b`.
+pub fn next_direct_id(conn: &mut Connection, a: &Uuid, b: &Uuid) -> Result<u64> {
+    Ok(conn.incr(direct_id_key(a, b), 1)?)
+}
+
+pub fn store_direct_message(conn: &mut Connection, msg: &TalkProtocol) -> Result<()> {
+    let TalkProtocol::DirectMessage {
+        uuid,
+        recipient_uuid,
+        message_id,
+        ..
+    } = msg
+    else {
+        return Ok(());
+    };
+    let bin = msg.serialize()?;
+    let _: () = conn.zadd(conversation_key(uuid, recipient_uuid), bin, *message_id)?;
+    Ok(())
+}
+
+/// Returns the latest `limit` direct messages between `a` and `b`, oldest first.
+pub fn fetch_conversation(
+    conn: &mut Connection,
+    a: &Uuid,
+    b: &Uuid,
+    limit: i64,
+) -> Result<Vec<TalkProtocol>> {
+    let limit = limit.min(MAX_FETCH_LIMIT);
+    if limit <= 0 {
+        return Ok(Vec::new());
+    }
+
+    let raw: Vec<Vec<u8>> =
+        conn.zrevrangebyscore_limit(conversation_key(a, b), "+inf", "-inf", 0, limit as isize)?;
+
+    Ok(raw
+        .iter()
+        .rev()
+        .filter_map(|bin| TalkProtocol::deserialize(bin).ok())
+        .collect())
+}
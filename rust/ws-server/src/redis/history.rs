@@ -0,0 +1,132 @@
+use anyhow::Result;
+use redis::{Commands, Connection};
+use std::env;
+use tuitalk_shared::TalkProtocol;
+
+// Upper bound for a single fetch, regardless of what the client asks for
+pub const MAX_FETCH_LIMIT: i64 = 100;
+const DEFAULT_REPLAY_LIMIT: i64 = 50;
+// Events kept per room, older ones are dropped as new ones arrive
+const MAX_HISTORY_EVENTS: isize = 5000;
+
+/// Number of events pushed to a connection when it joins a room.
+pub fn replay_limit() -> i64 {
+    env::var("HISTORY_REPLAY_LIMIT")
+        .ok()
+        .and_then(|limit| limit.parse().ok())
+        .unwrap_or(DEFAULT_REPLAY_LIMIT)
+}
+
+fn history_key(room_id: &str) -> String {
+    format!("history:{}", room_id)
+}
+
+fn message_id_key(room_id: &str) -> String {
+    format!("message_id:{}", room_id)
+}
+
+/// Next id of a room, shared by all server instances through redis.
+pub fn next_message_id(conn: &mut Connection, room_id: &str) -> Result<u64> {
+    Ok(conn.incr(message_id_key(room_id), 1)?)
+}
+
+/// Id of the newest event of a room, 0 before the first one.
+pub fn current_message_id(conn: &mut Connection, room_id: &str) -> Result<u64> {
+    let current: Option<u64> = conn.get(message_id_key(room_id))?;
+    Ok(current.unwrap_or_default())
+}
+
+// Events are scored by their message id, so the history keeps the server's order
+pub fn store_event(conn: &mut Connection, msg: &TalkProtocol, room_id: &str) -> Result<()> {
+    let Some(message_id) = msg.message_id() else {
+        return Ok(());
+    };
+    let bin = msg.serialize()?;
+    let key = history_key(room_id);
+    let _: () = conn.zadd(&key, bin, message_id)?;
+    let _: () = conn.zremrangebyrank(&key, 0, -(MAX_HISTORY_EVENTS + 1))?;
+    Ok(())
+}
+
+/// The event stored under `message_id`, if the room still has it.
+pub fn fetch_event(
+    conn: &mut Connection,
+    room_id: &str,
+    message_id: u64,
+) -> Result<Option<TalkProtocol>> {
+    let raw: Vec<Vec<u8>> = conn.zrangebyscore(history_key(room_id), message_id, message_id)?;
+    Ok(raw
+        .iter()
+        .find_map(|bin| TalkProtocol::deserialize(bin).ok()))
+}
+
+/// Swaps the stored event for `msg`, which keeps the id of the event it replaces.
+pub fn replace_event(conn: &mut Connection, msg: &TalkProtocol, room_id: &str) -> Result<()> {
+    let Some(message_id) = msg.message_id() else {
+        return Ok(());
+    };
+    let _: () = conn.zrembyscore(history_key(room_id), message_id, message_id)?;
+    store_event(conn, msg, room_id)
+}
+
+/// Returns up to `limit` events with an id below `before`, oldest first.
+pub fn fetch_history(
+    conn: &mut Connection,
+    room_id: &str,
+    limit: i64,
+    before: u64,
+) -> Result<Vec<TalkProtocol>> {
+    let limit = limit.min(MAX_FETCH_LIMIT);
+    fetch_range(conn, room_id, limit, format!("({}", before))
+}
+
+/// Returns up to `limit` events with an id above `after`, oldest first.
+pub fn fetch_since(
+    conn: &mut Connection,
+    room_id: &str,
+    limit: i64,
+    after: u64,
+) -> Result<Vec<TalkProtocol>> {
+    let limit = limit.min(MAX_FETCH_LIMIT);
+    if limit <= 0 {
+        return Ok(Vec::new());
+    }
+
+    let raw: Vec<Vec<u8>> = conn.zrangebyscore_limit(
+        history_key(room_id),
+        format!("({}", after),
+        "+inf",
+        0,
+        limit as isize,
+    )?;
+
+    Ok(raw
+        .iter()
+        .filter_map(|bin| TalkProtocol::deserialize(bin).ok())
+        .collect())
+}
+
+/// Returns the latest `limit` events of a room, oldest first.
+pub fn fetch_latest(conn: &mut Connection, room_id: &str, limit: i64) -> Result<Vec<TalkProtocol>> {
+    fetch_range(conn, room_id, limit, "+inf".to_string())
+}
+
+fn fetch_range(
+    conn: &mut Connection,
+    room_id: &str,
+    limit: i64,
+    max: String,
+) -> Result<Vec<TalkProtocol>> {
+    if limit <= 0 {
+        return Ok(Vec::new());
+    }
+
+    let raw: Vec<Vec<u8>> =
+        conn.zrevrangebyscore_limit(history_key(room_id), max, "-inf", 0, limit as isize)?;
+
+    Ok(raw
+        .iter()
+        .rev()
+        .filter_map(|bin| TalkProtocol::deserialize(bin).ok())
+        .collect())
+}
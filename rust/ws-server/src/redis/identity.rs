@@ -0,0 +1,14 @@
+use anyhow::Result;
+use redis::{Commands, Connection};
+use uuid::Uuid;
+
+fn identity_key(uuid: &Uuid) -> String {
+    format!("identity:{}", uuid)
+}
+
+/// Binds `public_key` to `uuid` on first use and afterwards only accepts that same key.
+pub fn bind_public_key(conn: &mut Connection, uuid: &Uuid, public_key: &[u8]) -> Result<bool> {
+    let _: bool = conn.set_nx(identity_key(uuid), public_key)?;
+    let bound: Vec<u8> = conn.get(identity_key(uuid))?;
+    Ok(bound == public_key)
+}
@@ -1,18 +1,36 @@
+pub mod accounts;
+pub mod direct;
+pub mod history;
+pub mod identity;
+pub mod moderation;
+pub mod presence;
+pub mod ratelimit;
+pub mod receipts;
+pub mod rooms;
+
 use futures_util::StreamExt;
 use redis::Client;
 use redis::Connection;
 use redis::aio::PubSub;
-use std::{sync::Arc, env};
+use std::{collections::HashSet, env, sync::Arc};
 use tokio::sync::oneshot::Sender;
 use tokio::sync::{
     Mutex as TMutex,
     mpsc::{UnboundedReceiver as TUnboundedReceiver, UnboundedSender as TUnboundedSender},
 };
 use tokio_tungstenite::tungstenite::protocol::Message;
-use tuitalk_shared::TalkProtocol;
+use tuitalk_shared::{ModerationAction, TalkProtocol};
+use uuid::Uuid;
 
 pub type SharedRedis = Arc<TMutex<Connection>>;
 
+/// Changes to the channels a connection listens on, its rooms and the inbox of its user.
+pub enum Subscription {
+    JoinRoom(String),
+    LeaveRoom(String),
+    User(Uuid),
+}
+
 pub async fn create_redis_async_pubsub_connection() -> Result<PubSub, redis::RedisError> {
     let node_env = env::var("REDIS_NODES").unwrap_or(
         "localhost:7001".to_string()
@@ -35,9 +53,13 @@ pub async fn create_redis_connection() -> Result<Connection, redis::RedisError>
     Ok(publish_conn)
 }
 
+/// A moderator removed `uuid` from the room, sent to the connection so it stops listening to it.
+pub type Removal = (String, Uuid);
+
 pub async fn subscribe_to_redis(
     tx: TUnboundedSender<Message>,
-    mut room_id_receiver: TUnboundedReceiver<(i32, Sender<()>)>,
+    mut subscription_receiver: TUnboundedReceiver<(Subscription, Sender<()>)>,
+    removed_tx: TUnboundedSender<Removal>,
 ) {
     println!("[REDIS] Subbing to redis");
 
@@ -55,6 +77,15 @@ pub async fn subscribe_to_redis(
                 bincode::deserialize::<TalkProtocol>(message.get_payload_bytes())
             {
                 println!("[REDIS] Received {:?}", deserialized);
+                if let TalkProtocol::Moderated {
+                    room_id,
+                    target_uuid,
+                    action: ModerationAction::Kicked | ModerationAction::Banned,
+                    ..
+                } = &deserialized
+                {
+                    let _ = removed_tx.send((room_id.clone(), *target_uuid));
+                }
                 let _ = tx_clone.send(Message::Binary(deserialized.serialize().unwrap().into()));
             } else {
                 eprintln!("Failed to deserialize message from Redis");
@@ -62,24 +93,40 @@ pub async fn subscribe_to_redis(
         }
     });
 
-    // track currently active room
-    let mut current_room: Option<String> = None;
+    // track joined rooms and the current user
+    let mut rooms: HashSet<String> = HashSet::new();
+    let mut current_user: Option<String> = None;
 
-    // listen on channel for room changes
-    while let Some((room_id, ack)) = room_id_receiver.recv().await {
-        let channel = format!("{}", room_id);
+    // listen on channel for room and user changes
+    while let Some((subscription, ack)) = subscription_receiver.recv().await {
+        match subscription {
+            Subscription::JoinRoom(room_id) => {
+                let channel = rooms::room_channel(&room_id);
+                if rooms.insert(channel.clone()) {
+                    println!("[REDIS] Subscribing to {}", channel);
+                    sink.subscribe(&channel).await.expect("SSUBSCRIBE failed");
+                }
+            }
+            Subscription::LeaveRoom(room_id) => {
+                let channel = rooms::room_channel(&room_id);
+                if rooms.remove(&channel) {
+                    println!("[REDIS] Unsubscribing from {}", channel);
+                    let _ = sink.unsubscribe(&channel).await;
+                }
+            }
+            Subscription::User(uuid) => {
+                // unsubscribe from the old user if there was one
+                if let Some(old) = &current_user {
+                    println!("[REDIS] Unsubscribing from {}", old);
+                    let _ = sink.unsubscribe(old).await;
+                }
 
-        // unsubscribe from old room if there was one
-        if let Some(old) = &current_room {
-            println!("[REDIS] Unsubscribing from {}", old);
-            let _ = sink.unsubscribe(old).await;
+                let channel = direct::user_channel(&uuid);
+                println!("[REDIS] Subscribing to {}", channel);
+                sink.subscribe(&channel).await.expect("SSUBSCRIBE failed");
+                current_user = Some(channel);
+            }
         }
-
-        // subscribe to new room
-        println!("[REDIS] Subscribing to {}", channel);
-        sink.subscribe(&channel).await.expect("SSUBSCRIBE failed");
-
-        current_room = Some(channel);
         let _ = ack.send(());
     }
 }
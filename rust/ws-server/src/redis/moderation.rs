@@ -0,0 +1,92 @@
+use crate::redis::rooms::creator_uuid;
+use anyhow::Result;
+use redis::{Commands, Connection};
+use std::collections::HashMap;
+use tuitalk_shared::RoomRole;
+use uuid::Uuid;
+
+// Moderators of a room, the owner is always the room's creator
+fn roles_key(room_id: &str) -> String {
+    format!("roles:{}", room_id)
+}
+
+// Banned uuids with the name they were banned under, so they can be unbanned by name
+fn bans_key(room_id: &str) -> String {
+    format!("bans:{}", room_id)
+}
+
+// Muted uuids with the time the mute ends
+fn mutes_key(room_id: &str) -> String {
+    format!("mutes:{}", room_id)
+}
+
+pub fn role(conn: &mut Connection, room_id: &str, uuid: &Uuid) -> Result<RoomRole> {
+    if creator_uuid(conn, room_id)? == Some(*uuid) {
+        return Ok(RoomRole::Owner);
+    }
+    let role: Option<String> = conn.hget(roles_key(room_id), uuid.to_string())?;
+    Ok(match role.as_deref() {
+        Some("moderator") => RoomRole::Moderator,
+        _ => RoomRole::Member,
+    })
+}
+
+/// Makes the user a moderator or a plain member again, ownership can't be handed out.
+pub fn set_role(conn: &mut Connection, room_id: &str, uuid: &Uuid, role: RoomRole) -> Result<()> {
+    let key = roles_key(room_id);
+    match role {
+        RoomRole::Moderator => {
+            let _: () = conn.hset(key, uuid.to_string(), "moderator")?;
+        }
+        RoomRole::Member | RoomRole::Owner => {
+            let _: () = conn.hdel(key, uuid.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+pub fn ban(conn: &mut Connection, room_id: &str, uuid: &Uuid, username: &str) -> Result<()> {
+    let _: () = conn.hset(bans_key(room_id), uuid.to_string(), username)?;
+    Ok(())
+}
+
+pub fn unban(conn: &mut Connection, room_id: &str, uuid: &Uuid) -> Result<bool> {
+    let removed: usize = conn.hdel(bans_key(room_id), uuid.to_string())?;
+    Ok(removed > 0)
+}
+
+pub fn is_banned(conn: &mut Connection, room_id: &str, uuid: &Uuid) -> Result<bool> {
+    Ok(conn.hexists(bans_key(room_id), uuid.to_string())?)
+}
+
+/// Finds a banned user by the name they were banned under.
+pub fn banned_uuid(conn: &mut Connection, room_id: &str, username: &str) -> Result<Option<Uuid>> {
+    let bans: HashMap<String, String> = conn.hgetall(bans_key(room_id))?;
+    Ok(bans
+        .into_iter()
+        .find(|(_, banned)| banned.eq_ignore_ascii_case(username))
+        .and_then(|(uuid, _)| Uuid::parse_str(&uuid).ok()))
+}
+
+pub fn mute(conn: &mut Connection, room_id: &str, uuid: &Uuid, until: u64) -> Result<()> {
+    let _: () = conn.hset(mutes_key(room_id), uuid.to_string(), until)?;
+    Ok(())
+}
+
+/// End of the user's mute, `None` once it ran out.
+pub fn muted_until(
+    conn: &mut Connection,
+    room_id: &str,
+    uuid: &Uuid,
+    now: u64,
+) -> Result<Option<u64>> {
+    let until: Option<u64> = conn.hget(mutes_key(room_id), uuid.to_string())?;
+    match until {
+        Some(until) if until > now => Ok(Some(until)),
+        Some(_) => {
+            let _: () = conn.hdel(mutes_key(room_id), uuid.to_string())?;
+            Ok(None)
+        }
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::Result;
+use redis::{Commands, Connection};
+use std::collections::HashSet;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tuitalk_shared::RoomMember;
+use uuid::Uuid;
+
+// Connections refresh their rooms on every heartbeat, members of a server that died
+// without cleaning up drop out once they were not refreshed for this long
+const MEMBER_TTL_SECONDS: u64 = 90;
+
+fn members_key(room_id: &str) -> String {
+    format!("members:{}", room_id)
+}
+
+// When each member was last refreshed
+fn seen_key(room_id: &str) -> String {
+    format!("members_seen:{}", room_id)
+}
+
+// How many connections of each member are in the room, a user can be connected from several devices
+fn connections_key(room_id: &str) -> String {
+    format!("members_connections:{}", room_id)
+}
+
+fn now() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+/// Adds a connection of the member to the room.
+pub fn add_member(conn: &mut Connection, room_id: &str, uuid: &Uuid, username: &str) -> Result<()> {
+    let _: () = conn.hincr(connections_key(room_id), uuid.to_string(), 1)?;
+    rename_member(conn, room_id, uuid, username)
+}
+
+/// Updates the name the member is listed under.
+pub fn rename_member(
+    conn: &mut Connection,
+    room_id: &str,
+    uuid: &Uuid,
+    username: &str,
+) -> Result<()> {
+    let _: () = conn.hset(members_key(room_id), uuid.to_string(), username)?;
+    refresh_member(conn, room_id, uuid)
+}
+
+/// Keeps the member present for another `MEMBER_TTL_SECONDS`.
+pub fn refresh_member(conn: &mut Connection, room_id: &str, uuid: &Uuid) -> Result<()> {
+    let _: () = conn.zadd(seen_key(room_id), uuid.to_string(), now()?)?;
+    // a room nobody refreshes any more disappears as a whole
+    let ttl = MEMBER_TTL_SECONDS as i64;
+    let _: () = conn.expire(members_key(room_id), ttl)?;
+    let _: () = conn.expire(seen_key(room_id), ttl)?;
+    let _: () = conn.expire(connections_key(room_id), ttl)?;
+    Ok(())
+}
+
+/// Takes a connection of the member out of the room, true once the last one is gone.
+pub fn leave_member(conn: &mut Connection, room_id: &str, uuid: &Uuid) -> Result<bool> {
+    let connections: i64 = conn.hincr(connections_key(room_id), uuid.to_string(), -1)?;
+    if connections > 0 {
+        return Ok(false);
+    }
+    remove_member(conn, room_id, uuid)?;
+    Ok(true)
+}
+
+/// Removes the member with all of their connections, as a kick or a ban does.
+pub fn remove_member(conn: &mut Connection, room_id: &str, uuid: &Uuid) -> Result<()> {
+    let _: () = conn.hdel(members_key(room_id), uuid.to_string())?;
+    let _: () = conn.zrem(seen_key(room_id), uuid.to_string())?;
+    let _: () = conn.hdel(connections_key(room_id), uuid.to_string())?;
+    Ok(())
+}
+
+// Drops the members that were not refreshed in time
+fn prune_members(conn: &mut Connection, room_id: &str) -> Result<()> {
+    let stale_before = now()?.saturating_sub(MEMBER_TTL_SECONDS);
+    let fresh: HashSet<String> = conn.zrangebyscore(seen_key(room_id), stale_before, "+inf")?;
+    let stale: Vec<String> = conn
+        .hkeys::<_, Vec<String>>(members_key(room_id))?
+        .into_iter()
+        .filter(|uuid| !fresh.contains(uuid))
+        .collect();
+    if !stale.is_empty() {
+        let _: () = conn.hdel(members_key(room_id), &stale)?;
+        let _: () = conn.hdel(connections_key(room_id), &stale)?;
+    }
+    let _: () = conn.zrembyscore(seen_key(room_id), "-inf", format!("({}", stale_before))?;
+    Ok(())
+}
+
+pub fn is_member(conn: &mut Connection, room_id: &str, uuid: &Uuid) -> Result<bool> {
+    prune_members(conn, room_id)?;
+    Ok(conn.hexists(members_key(room_id), uuid.to_string())?)
+}
+
+pub fn count_members(conn: &mut Connection, room_id: &str) -> Result<usize> {
+    prune_members(conn, room_id)?;
+    Ok(conn.hlen(members_key(room_id))?)
+}
+
+pub fn list_members(conn: &mut Connection, room_id: &str) -> Result<Vec<RoomMember>> {
+    prune_members(conn, room_id)?;
+    let raw: Vec<(String, String)> = conn.hgetall(members_key(room_id))?;
+    let mut members: Vec<RoomMember> = raw
+        .into_iter()
+        .filter_map(|(uuid, username)| {
+            Some(RoomMember {
+                uuid: Uuid::parse_str(&uuid).ok()?,
+                username,
+            })
+        })
+        .collect();
+    members.sort_by(|a, b| a.username.cmp(&b.username));
+    Ok(members)
+}
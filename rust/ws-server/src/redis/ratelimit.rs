@@ -0,0 +1,39 @@
+use crate::ratelimit::RateLimitConfig;
+use anyhow::Result;
+use redis::{Connection, Script};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+// Refills and takes from the bucket atomically so all server instances share it
+const TAKE_TOKEN: &str = r"
+local burst = tonumber(ARGV[1])
+local per_second = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+local tokens = tonumber(bucket[1]) or burst
+local updated = tonumber(bucket[2]) or now
+tokens = math.min(burst, tokens + (now - updated) / 1000 * per_second)
+local allowed = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    allowed = 1
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated', now)
+redis.call('PEXPIRE', KEYS[1], math.ceil(burst / per_second * 1000) + 1000)
+return allowed
+";
+
+fn ratelimit_key(uuid: &Uuid) -> String {
+    format!("ratelimit:{}", uuid)
+}
+
+pub fn take_token(conn: &mut Connection, uuid: &Uuid, config: &RateLimitConfig) -> Result<bool> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+    let allowed: i32 = Script::new(TAKE_TOKEN)
+        .key(ratelimit_key(uuid))
+        .arg(config.burst)
+        .arg(config.per_second)
+        .arg(now)
+        .invoke(conn)?;
+    Ok(allowed == 1)
+}
@@ -0,0 +1,35 @@
+use anyhow::Result;
+use redis::{Commands, Connection};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+// Newest message id each user of the room has read
+fn read_key(room_id: &str) -> String {
+    format!("read:{}", room_id)
+}
+
+/// Moves the user's read position forward, false if they already read further.
+pub fn mark_read(
+    conn: &mut Connection,
+    room_id: &str,
+    uuid: &Uuid,
+    message_id: u64,
+) -> Result<bool> {
+    if last_read(conn, room_id, uuid)?.is_some_and(|read| read >= message_id) {
+        return Ok(false);
+    }
+    let _: () = conn.hset(read_key(room_id), uuid.to_string(), message_id)?;
+    Ok(true)
+}
+
+pub fn last_read(conn: &mut Connection, room_id: &str, uuid: &Uuid) -> Result<Option<u64>> {
+    Ok(conn.hget(read_key(room_id), uuid.to_string())?)
+}
+
+pub fn read_positions(conn: &mut Connection, room_id: &str) -> Result<HashMap<Uuid, u64>> {
+    let raw: HashMap<String, u64> = conn.hgetall(read_key(room_id))?;
+    Ok(raw
+        .into_iter()
+        .filter_map(|(uuid, message_id)| Some((Uuid::parse_str(&uuid).ok()?, message_id)))
+        .collect())
+}
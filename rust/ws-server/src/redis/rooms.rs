@@ -0,0 +1,176 @@
+use crate::redis::presence::count_members;
+use anyhow::Result;
+use redis::{Commands, Connection};
+use std::collections::HashMap;
+use tuitalk_shared::{DEFAULT_ROOM, RoomAccess, RoomMetadata, RoomSummary};
+use uuid::Uuid;
+
+// Rooms scored by the time of their latest event
+const REGISTRY_KEY: &str = "rooms";
+// Rooms without members are listed while their last event is younger than this
+const ACTIVE_WINDOW_MS: u64 = 7 * 24 * 60 * 60 * 1000;
+const MAX_LISTED_ROOMS: isize = 50;
+
+fn room_meta_key(room_id: &str) -> String {
+    format!("room:{}", room_id)
+}
+
+fn invites_key(room_id: &str) -> String {
+    format!("invites:{}", room_id)
+}
+
+fn access_to_str(access: RoomAccess) -> &'static str {
+    match access {
+        RoomAccess::Public => "public",
+        RoomAccess::Password => "password",
+        RoomAccess::InviteOnly => "invite",
+    }
+}
+
+fn access_from_str(access: &str) -> RoomAccess {
+    match access {
+        "password" => RoomAccess::Password,
+        "invite" => RoomAccess::InviteOnly,
+        _ => RoomAccess::Public,
+    }
+}
+
+/// Pub/sub channel the events of a room are published on.
+pub fn room_channel(room_id: &str) -> String {
+    format!("room:{}", room_id)
+}
+
+/// Creates the room on its first join, the joining user becomes its creator
+/// unless it is the default room everyone joins.
+pub fn ensure_room(
+    conn: &mut Connection,
+    room_id: &str,
+    display_name: &str,
+    creator: &str,
+    creator_uuid: &Uuid,
+    created: u64,
+) -> Result<RoomMetadata> {
+    let key = room_meta_key(room_id);
+    // whoever happens to connect first must not own the room everyone lands in
+    let creator = if room_id == DEFAULT_ROOM { "" } else { creator };
+    let new: bool = conn.hset_nx(&key, "created", created)?;
+    if new {
+        let _: () = conn.hset(&key, "display_name", display_name)?;
+        if !creator.is_empty() {
+            let _: () = conn.hset_multiple(
+                &key,
+                &[
+                    ("creator", creator),
+                    ("creator_uuid", &creator_uuid.to_string()),
+                ],
+            )?;
+        }
+    }
+    Ok(room_metadata(conn, room_id)?.unwrap_or(RoomMetadata {
+        room_id: room_id.to_string(),
+        display_name: display_name.to_string(),
+        topic: None,
+        created,
+        creator: creator.to_string(),
+        access: RoomAccess::Public,
+    }))
+}
+
+pub fn room_metadata(conn: &mut Connection, room_id: &str) -> Result<Option<RoomMetadata>> {
+    let fields: HashMap<String, String> = conn.hgetall(room_meta_key(room_id))?;
+    let Some(created) = fields.get("created").and_then(|c| c.parse().ok()) else {
+        return Ok(None);
+    };
+    Ok(Some(RoomMetadata {
+        room_id: room_id.to_string(),
+        display_name: fields
+            .get("display_name")
+            .cloned()
+            .unwrap_or_else(|| room_id.to_string()),
+        topic: fields.get("topic").cloned(),
+        created,
+        creator: fields.get("creator").cloned().unwrap_or_default(),
+        access: fields
+            .get("access")
+            .map(|access| access_from_str(access))
+            .unwrap_or_default(),
+    }))
+}
+
+pub fn set_topic(conn: &mut Connection, room_id: &str, topic: Option<&str>) -> Result<()> {
+    let _: () = match topic {
+        Some(topic) => conn.hset(room_meta_key(room_id), "topic", topic)?,
+        None => conn.hdel(room_meta_key(room_id), "topic")?,
+    };
+    Ok(())
+}
+
+/// Marks the room as active, called for every event published to it.
+pub fn touch_room(conn: &mut Connection, room_id: &str, time: u64) -> Result<()> {
+    let _: () = conn.zadd(REGISTRY_KEY, room_id, time)?;
+    Ok(())
+}
+
+/// Rooms with members or recent events, most recently active first.
+pub fn list_rooms(conn: &mut Connection, now: u64) -> Result<Vec<RoomSummary>> {
+    let registered: Vec<(String, u64)> =
+        conn.zrevrange_withscores(REGISTRY_KEY, 0, MAX_LISTED_ROOMS - 1)?;
+
+    let mut rooms = Vec::new();
+    for (room_id, last_activity) in registered {
+        let members = count_members(conn, &room_id)?;
+        if members == 0 && now.saturating_sub(last_activity) > ACTIVE_WINDOW_MS {
+            continue;
+        }
+        let Some(metadata) = room_metadata(conn, &room_id)? else {
+            continue;
+        };
+        rooms.push(RoomSummary {
+            room_id,
+            display_name: metadata.display_name,
+            topic: metadata.topic,
+            members,
+            last_activity,
+            access: metadata.access,
+        });
+    }
+    Ok(rooms)
+}
+
+pub fn creator_uuid(conn: &mut Connection, room_id: &str) -> Result<Option<Uuid>> {
+    let uuid: Option<String> = conn.hget(room_meta_key(room_id), "creator_uuid")?;
+    Ok(uuid.and_then(|uuid| Uuid::parse_str(&uuid).ok()))
+}
+
+/// Changes who may join, a password protected room keeps the hash of its password.
+pub fn set_access(
+    conn: &mut Connection,
+    room_id: &str,
+    access: RoomAccess,
+    password_hash: Option<&str>,
+) -> Result<()> {
+    let key = room_meta_key(room_id);
+    let _: () = conn.hset(&key, "access", access_to_str(access))?;
+    match (access, password_hash) {
+        (RoomAccess::Password, Some(password_hash)) => {
+            let _: () = conn.hset(&key, "password_hash", password_hash)?;
+        }
+        _ => {
+            let _: () = conn.hdel(&key, "password_hash")?;
+        }
+    }
+    Ok(())
+}
+
+pub fn password_hash(conn: &mut Connection, room_id: &str) -> Result<Option<String>> {
+    Ok(conn.hget(room_meta_key(room_id), "password_hash")?)
+}
+
+pub fn invite(conn: &mut Connection, room_id: &str, uuid: &Uuid) -> Result<()> {
+    let _: () = conn.sadd(invites_key(room_id), uuid.to_string())?;
+    Ok(())
+}
+
+pub fn is_invited(conn: &mut Connection, room_id: &str, uuid: &Uuid) -> Result<bool> {
+    Ok(conn.sismember(invites_key(room_id), uuid.to_string())?)
+}
@@ -1,13 +1,90 @@
+use crate::frame::{Frame, FrameError, decode};
+use crate::ratelimit::RateLimiter;
+use crate::redis::accounts::{
+    Account, create_token, find_account, hash_password_blocking, owner_of, register, resolve_token,
+    verify_password_blocking,
+};
+use crate::redis::direct::{
+    fetch_conversation, next_direct_id, store_direct_message, user_channel,
+};
+use crate::redis::history::{
+    current_message_id, fetch_event, fetch_history, fetch_latest, fetch_since, next_message_id,
+    replace_event, replay_limit, store_event,
+};
+use crate::redis::identity::bind_public_key;
+use crate::redis::moderation::{
+    ban, banned_uuid, is_banned, mute, muted_until, role, set_role, unban,
+};
+use crate::redis::presence::{
+    add_member, is_member, leave_member, list_members, refresh_member, remove_member, rename_member,
+};
+use crate::redis::ratelimit::take_token;
+use crate::redis::receipts::{last_read, mark_read, read_positions};
+use crate::redis::rooms::{
+    creator_uuid, ensure_room, invite, is_invited, list_rooms, password_hash, room_channel,
+    room_metadata, set_access, set_topic, touch_room,
+};
 use crate::redis::*;
 use anyhow::Result;
+use ed25519_dalek::{Signature, VerifyingKey};
 use futures_util::{SinkExt, StreamExt, stream::TryStreamExt};
-use redis::Commands;
-use std::{env, net::SocketAddr, sync::Arc};
+use rand_core::{OsRng, RngCore};
+use redis::{Commands, Connection};
+use std::{
+    env,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::Mutex as TMutex;
 use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::{mpsc::unbounded_channel, oneshot};
-use tuitalk_shared::TalkProtocol;
+use tokio_tungstenite::tungstenite::protocol::{
+    CloseFrame, Message, WebSocketConfig, frame::coding::CloseCode,
+};
+use tuitalk_shared::mention::resolve_mentions;
+use tuitalk_shared::validation::{
+    MAX_FRAME_SIZE, MAX_MUTE_SECONDS, MAX_REACTIONS, ValidationError, room_key, validate_message,
+    validate_password, validate_reaction, validate_room_name, validate_topic, validate_username,
+};
+use tuitalk_shared::{
+    DEFAULT_ROOM, MIN_PROTOCOL_VERSION, ModerationAction, PROTOCOL_VERSION, ReadReceipt,
+    RoomAccess, RoomRole, TalkMessage, TalkProtocol, auth_payload, capability, error_code,
+};
+use uuid::Uuid;
+
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+const PONG_TIMEOUT: Duration = Duration::from_secs(45);
+const CHALLENGE_LENGTH: usize = 32;
+// Oversized frames below this are answered with an error, above it tungstenite drops the connection
+const HARD_FRAME_LIMIT: usize = MAX_FRAME_SIZE * 4;
+// Malformed frames a connection may send before it is closed
+const MAX_FRAME_VIOLATIONS: u32 = 3;
+
+// What the server knows about the user behind a connection,
+// `device_uuid` is only set once the client proved it owns it and
+// `uuid` switches to the account's identity after a login
+#[derive(Default)]
+struct Session {
+    rooms: Vec<String>,
+    uuid: Option<Uuid>,
+    device_uuid: Option<Uuid>,
+    account: Option<String>,
+    challenge: Vec<u8>,
+    username: String,
+    greeted: bool,
+    closing: bool,
+    frame_violations: u32,
+    capabilities: Vec<String>,
+    limiter: RateLimiter,
+}
+
+impl Session {
+    fn supports(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
 
 pub async fn handle_connection(
     raw_stream: TcpStream,
@@ -16,27 +93,76 @@ pub async fn handle_connection(
 ) -> Result<()> {
     println!("[SERVER] Incoming TCP connection from: {}", addr);
 
-    let ws_stream = tokio_tungstenite::accept_async(raw_stream).await?;
+    let config = WebSocketConfig::default()
+        .max_message_size(Some(HARD_FRAME_LIMIT))
+        .max_frame_size(Some(HARD_FRAME_LIMIT));
+    let ws_stream = tokio_tungstenite::accept_async_with_config(raw_stream, Some(config)).await?;
     println!("[SERVER] WebSocket connection established: {}", addr);
 
     let (tx, mut rx) = unbounded_channel();
-    let (room_tx, room_rx) = unbounded_channel::<(i32, oneshot::Sender<()>)>();
+    let (subscribe_tx, subscribe_rx) = unbounded_channel::<(Subscription, oneshot::Sender<()>)>();
+    let (removed_tx, mut removed_rx) = unbounded_channel::<Removal>();
 
-    let (mut outgoing, incoming) = ws_stream.split();
+    let (mut outgoing, mut incoming) = ws_stream.split();
+    let mut session = Session::default();
+    let last_seen = Mutex::new(Instant::now());
 
     // Spawn Redis subscriber
-    tokio::spawn(subscribe_to_redis(tx.clone(), room_rx));
+    tokio::spawn(subscribe_to_redis(tx.clone(), subscribe_rx, removed_tx));
 
-    // Process incoming messages
+    // Process incoming messages and removals by moderators, and keep the session's
+    // presence fresh at the pace of the heartbeat
     let message_handler = async {
-        incoming
-            .try_for_each(|msg| async {
-                let deserialize_msg: TalkProtocol =
-                    bincode::deserialize(&msg.into_data()).expect("deserializing");
-                let _ = handle_message(deserialize_msg, &room_tx, &shared_redis).await;
-                Ok(())
-            })
-            .await
+        let mut presence_refresh = tokio::time::interval(PING_INTERVAL);
+        loop {
+            tokio::select! {
+                msg = incoming.try_next() => {
+                    let Some(msg) = msg? else {
+                        break;
+                    };
+                    *last_seen.lock().expect("last seen") = Instant::now();
+                    match decode(msg) {
+                        Frame::Protocol(msg) => {
+                            if let Err(e) =
+                                handle_message(msg, &mut session, &tx, &subscribe_tx, &shared_redis)
+                                    .await
+                            {
+                                eprintln!("[SERVER] Error handling frame from {}: {}", addr, e);
+                            }
+                        }
+                        Frame::Invalid(error) => handle_invalid_frame(&mut session, &tx, error)?,
+                        Frame::Close => break,
+                        Frame::Control => {}
+                    }
+                }
+                _ = presence_refresh.tick() => {
+                    if let Err(e) = handle_presence_refresh(&session, &shared_redis).await {
+                        eprintln!("[SERVER] Error refreshing presence of {}: {}", addr, e);
+                    }
+                }
+                Some((room_id, target)) = removed_rx.recv() => {
+                    if let Err(e) =
+                        handle_removed(&mut session, &room_id, &target, &subscribe_tx, &shared_redis)
+                            .await
+                    {
+                        eprintln!("[SERVER] Error removing {} from {}: {}", addr, room_id, e);
+                    }
+                }
+            }
+        }
+        Ok(())
+    };
+
+    // Ping the client and give up once it stops answering
+    let heartbeat = async {
+        let mut interval = tokio::time::interval(PING_INTERVAL);
+        loop {
+            interval.tick().await;
+            if last_seen.lock().expect("last seen").elapsed() > PONG_TIMEOUT {
+                return Err(anyhow::anyhow!("heartbeat timed out"));
+            }
+            tx.send(Message::Ping(Vec::new().into()))?;
+        }
     };
 
     // Forward Redis messages to WebSocket
@@ -48,35 +174,227 @@ pub async fn handle_connection(
     };
 
     // Run both tasks concurrently
-    tokio::select! {
-        result = message_handler => result,
-        result = redis_forwarder => result,
-    }?;
+    let (result, reason) = tokio::select! {
+        result = message_handler => (result, "connection lost"),
+        result = redis_forwarder => (result, "connection lost"),
+        result = heartbeat => (result, "connection timed out"),
+    };
+
+    if let Err(e) = handle_disconnect(&session, reason, &shared_redis).await {
+        eprintln!("[SERVER] Cleanup error for {}: {}", addr, e);
+    }
 
     println!("{} disconnected", addr);
+    result
+}
+
+// Rooms the session is still in never got a LeaveRoom, so announce it for the client
+// unless the user is still in the room from another connection
+async fn handle_disconnect(
+    session: &Session,
+    reason: &str,
+    shared_redis: &SharedRedis,
+) -> Result<()> {
+    let Some(uuid) = session.uuid else {
+        return Ok(());
+    };
+    for room_id in &session.rooms {
+        let gone = {
+            let mut conn = shared_redis.lock().await;
+            leave_member(&mut conn, room_id, &uuid)?
+        };
+        if !gone {
+            continue;
+        }
+
+        let response = TalkProtocol::UserLeft {
+            uuid,
+            username: session.username.clone(),
+            room_id: room_id.to_string(),
+            unixtime: get_unix_timestamp()?,
+            reason: Some(reason.to_string()),
+            message_id: 0,
+        };
+        publish_message(shared_redis, &response, room_id).await?;
+    }
     Ok(())
 }
 
+fn get_unix_timestamp() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64)
+}
+
 async fn handle_message(
-    msg: TalkProtocol,
-    room_tx: &UnboundedSender<(i32, oneshot::Sender<()>)>,
+    mut msg: TalkProtocol,
+    session: &mut Session,
+    tx: &UnboundedSender<Message>,
+    subscribe_tx: &UnboundedSender<(Subscription, oneshot::Sender<()>)>,
     shared_redis: &SharedRedis,
 ) -> Result<()> {
-    println!("[SERVER] Received {:?}", msg);
+    println!("[SERVER] Received {:?}", msg.redacted());
+    if session.closing {
+        return Ok(());
+    }
+    if !session.greeted {
+        return match &msg {
+            TalkProtocol::Hello {
+                protocol_version,
+                client_name,
+                capabilities,
+            } => handle_hello(session, *protocol_version, client_name, capabilities, tx),
+            _ => close_with_error(
+                session,
+                tx,
+                error_code::HANDSHAKE_REQUIRED,
+                "the first frame has to be Hello",
+            ),
+        };
+    }
+    if session.uuid.is_none() {
+        return match &msg {
+            TalkProtocol::Authenticate {
+                uuid,
+                public_key,
+                signature,
+            } => {
+                handle_authenticate(session, uuid, public_key, signature, tx, shared_redis).await?;
+                match session.uuid {
+                    Some(uuid) => handle_subscribe(Subscription::User(uuid), subscribe_tx).await,
+                    None => Ok(()),
+                }
+            }
+            _ => close_with_error(
+                session,
+                tx,
+                error_code::AUTH_REQUIRED,
+                "authenticate before sending other frames",
+            ),
+        };
+    }
+    if !check_rate_limit(session, tx, shared_redis).await? {
+        return Ok(());
+    }
+    if let Some(claimed) = msg.sender_uuid() {
+        if Some(claimed) != session.uuid && Some(claimed) != session.device_uuid {
+            let response = TalkProtocol::Error {
+                code: error_code::IDENTITY_MISMATCH.to_string(),
+                message: "the frame names a different uuid than this connection".to_string(),
+            };
+            return send_to_client(tx, &response);
+        }
+        if let Some(uuid) = session.uuid {
+            msg.set_sender_uuid(uuid);
+        }
+    }
+    // a join with an unusable name keeps working under a guest name, anything else is refused
+    if let Err(error) = validate_frame(&msg) {
+        let response = TalkProtocol::Error {
+            code: error.code().to_string(),
+            message: error.to_string(),
+        };
+        send_to_client(tx, &response)?;
+        match &mut msg {
+            TalkProtocol::JoinRoom { username, uuid, .. }
+                if error != ValidationError::RoomNameInvalid =>
+            {
+                *username = guest_name(uuid)
+            }
+            _ => return Ok(()),
+        }
+    }
+    // names owned by an account are only available to that account,
+    // a join keeps working under a guest name while a rename is refused
+    let requested = match &msg {
+        TalkProtocol::JoinRoom { username, .. } | TalkProtocol::ChangeName { username, .. } => {
+            Some(username.clone())
+        }
+        _ => None,
+    };
+    if let Some(requested) = requested
+        && !username_allowed(session, &requested, shared_redis).await?
+    {
+        send_reserved_error(&requested, tx)?;
+        match &mut msg {
+            TalkProtocol::JoinRoom { username, uuid, .. } => *username = guest_name(uuid),
+            _ => return Ok(()),
+        }
+    }
+    // messages are shown with the name the session joined or renamed to
+    if !session.username.is_empty() {
+        match &mut msg {
+            TalkProtocol::PostMessage { message } => message.username = session.username.clone(),
+            TalkProtocol::DirectMessage { username, .. }
+            | TalkProtocol::Typing { username, .. }
+            | TalkProtocol::MarkRead { username, .. } => *username = session.username.clone(),
+            _ => {}
+        }
+    }
+
+    // mentions are resolved against who is in the room right now
+    if let TalkProtocol::PostMessage { message } = &mut msg {
+        let members = {
+            let mut conn = shared_redis.lock().await;
+            list_members(&mut conn, &message.room_id)?
+        };
+        message.mentions = resolve_mentions(&message.text, &members);
+    }
+
     match &msg {
         TalkProtocol::JoinRoom {
-            room_id,
+            room_id: display_name,
             uuid,
             username,
             unixtime,
+            password,
         } => {
-            handle_join(room_id, room_tx).await?;
+            let room_id = &room_key(display_name);
+            let banned = {
+                let mut conn = shared_redis.lock().await;
+                is_banned(&mut conn, room_id, uuid)?
+            };
+            if banned {
+                let response = TalkProtocol::Error {
+                    code: error_code::BANNED.to_string(),
+                    message: format!("you are banned from {}", display_name),
+                };
+                return send_to_client(tx, &response);
+            }
+            if let Some(refused) =
+                check_room_access(room_id, uuid, password.as_deref(), shared_redis).await?
+            {
+                return send_to_client(tx, &refused);
+            }
+            let room = {
+                let mut conn = shared_redis.lock().await;
+                ensure_room(
+                    &mut conn,
+                    room_id,
+                    display_name,
+                    username,
+                    uuid,
+                    get_unix_timestamp()?,
+                )?
+            };
+            send_to_client(tx, &TalkProtocol::RoomInfo { room })?;
+
+            // subscribe before replaying so nothing published in between is missed,
+            // the client drops the events it then gets twice by their id.
+            // The read state comes first so the client knows where the new messages start
+            handle_subscribe(Subscription::JoinRoom(room_id.to_string()), subscribe_tx).await?;
+            if session.supports(capability::HISTORY) {
+                if session.supports(capability::RECEIPTS) {
+                    handle_read_state(room_id, uuid, tx, shared_redis).await?;
+                }
+                handle_replay(room_id, tx, shared_redis).await?;
+            }
+            handle_presence_join(session, room_id, uuid, username, shared_redis).await?;
 
             let response = TalkProtocol::UserJoined {
                 uuid: *uuid,
                 username: username.clone(),
-                room_id: *room_id,
+                room_id: room_id.to_string(),
                 unixtime: *unixtime,
+                message_id: 0,
             };
             publish_message(shared_redis, &response, room_id).await?;
         }
@@ -86,33 +404,363 @@ async fn handle_message(
             unixtime,
             username,
         } => {
+            // leaving a room that was never joined would announce into it
+            if !session.rooms.iter().any(|joined| joined == room_id) {
+                return Ok(());
+            }
+            let gone = handle_presence_leave(session, room_id, uuid, shared_redis).await?;
+            handle_subscribe(Subscription::LeaveRoom(room_id.to_string()), subscribe_tx).await?;
+            // the user is still in the room on another device
+            if !gone {
+                return Ok(());
+            }
+
             let response = TalkProtocol::UserLeft {
                 uuid: *uuid,
                 username: username.clone(),
-                room_id: *room_id,
+                room_id: room_id.to_string(),
                 unixtime: *unixtime,
+                reason: None,
+                message_id: 0,
             };
             publish_message(shared_redis, &response, room_id).await?;
         }
         TalkProtocol::PostMessage { message } => {
+            if !require_room(session, &message.room_id, tx)?
+                || !require_unmuted(session, &message.room_id, tx, shared_redis).await?
+            {
+                return Ok(());
+            }
+            if let Some(reply_to) = message.reply_to {
+                let replied = {
+                    let mut conn = shared_redis.lock().await;
+                    fetch_event(&mut conn, &message.room_id, reply_to)?
+                };
+                if !matches!(replied, Some(TalkProtocol::PostMessage { .. })) {
+                    let response = TalkProtocol::Error {
+                        code: error_code::MESSAGE_NOT_FOUND.to_string(),
+                        message: "the message you reply to does not exist".to_string(),
+                    };
+                    return send_to_client(tx, &response);
+                }
+            }
             publish_message(shared_redis, &msg, &message.room_id).await?;
         }
+        // the name belongs to the connection, so every joined room learns about it
         TalkProtocol::ChangeName {
-            room_id,
             uuid,
             username,
             unixtime,
             old_username,
+            ..
+        } => {
+            session.username = username.clone();
+            for room_id in &session.rooms {
+                {
+                    let mut conn = shared_redis.lock().await;
+                    rename_member(&mut conn, room_id, uuid, username)?;
+                }
+
+                let response = TalkProtocol::UsernameChanged {
+                    uuid: *uuid,
+                    username: username.clone(),
+                    old_username: old_username.clone(),
+                    room_id: room_id.to_string(),
+                    unixtime: *unixtime,
+                    message_id: 0,
+                };
+
+                publish_message(shared_redis, &response, room_id).await?;
+            }
+        }
+        TalkProtocol::Register { username, password } => {
+            let password_hash = hash_password_blocking(password).await?;
+            let account = {
+                let mut conn = shared_redis.lock().await;
+                register(
+                    &mut conn,
+                    username,
+                    &password_hash,
+                    &session.uuid.unwrap_or_default(),
+                )?
+            };
+            match account {
+                Some(account) => {
+                    handle_login(session, account, None, tx, subscribe_tx, shared_redis).await?
+                }
+                None => send_to_client(
+                    tx,
+                    &TalkProtocol::Error {
+                        code: error_code::USERNAME_TAKEN.to_string(),
+                        message: format!("an account named '{}' already exists", username),
+                    },
+                )?,
+            }
+        }
+        TalkProtocol::Login { username, password } => {
+            let stored = {
+                let mut conn = shared_redis.lock().await;
+                find_account(&mut conn, username)?
+            };
+            // the lock is released before the slow password check
+            let account = match stored {
+                Some((account, password_hash))
+                    if verify_password_blocking(password, &password_hash).await? =>
+                {
+                    Some(account)
+                }
+                _ => None,
+            };
+            match account {
+                Some(account) => {
+                    handle_login(session, account, None, tx, subscribe_tx, shared_redis).await?
+                }
+                None => send_to_client(
+                    tx,
+                    &TalkProtocol::Error {
+                        code: error_code::LOGIN_FAILED.to_string(),
+                        message: "unknown username or wrong password".to_string(),
+                    },
+                )?,
+            }
+        }
+        TalkProtocol::TokenLogin { token } => {
+            let account = {
+                let mut conn = shared_redis.lock().await;
+                resolve_token(&mut conn, token)?
+            };
+            match account {
+                Some(account) => {
+                    let token = Some(token.clone());
+                    handle_login(session, account, token, tx, subscribe_tx, shared_redis).await?
+                }
+                None => send_to_client(
+                    tx,
+                    &TalkProtocol::Error {
+                        code: error_code::INVALID_TOKEN.to_string(),
+                        message: "the saved login expired, please /login again".to_string(),
+                    },
+                )?,
+            }
+        }
+        TalkProtocol::Fetch {
+            room_id,
+            limit,
+            fetch_before,
+        } => {
+            if !require_capability(session, capability::HISTORY, tx)?
+                || !require_reader(session, room_id, tx, shared_redis).await?
+            {
+                return Ok(());
+            }
+            handle_fetch(room_id, *limit, *fetch_before, tx, shared_redis).await?;
+        }
+        TalkProtocol::FetchAfter {
+            room_id,
+            limit,
+            fetch_after,
+        } => {
+            if !require_capability(session, capability::RESUME, tx)?
+                || !require_reader(session, room_id, tx, shared_redis).await?
+            {
+                return Ok(());
+            }
+            let events = {
+                let mut conn = shared_redis.lock().await;
+                fetch_since(&mut conn, room_id, *limit, *fetch_after)?
+            };
+            send_to_client(
+                tx,
+                &TalkProtocol::History {
+                    room_id: room_id.to_string(),
+                    text: events,
+                },
+            )?;
+        }
+        TalkProtocol::DirectMessage { .. } => {
+            if !require_capability(session, capability::DIRECT, tx)? {
+                return Ok(());
+            }
+            handle_direct_message(session, msg.clone(), tx, shared_redis).await?;
+        }
+        TalkProtocol::FetchConversation { username, limit } => {
+            if !require_capability(session, capability::DIRECT, tx)? {
+                return Ok(());
+            }
+            handle_fetch_conversation(session, username, *limit, tx, shared_redis).await?;
+        }
+        TalkProtocol::EditMessage { room_id, .. } => {
+            if !require_room(session, room_id, tx)?
+                || !require_unmuted(session, room_id, tx, shared_redis).await?
+            {
+                return Ok(());
+            }
+            handle_change_message(session, &msg, tx, shared_redis).await?;
+        }
+        TalkProtocol::DeleteMessage { room_id, .. } => {
+            if !require_room(session, room_id, tx)? {
+                return Ok(());
+            }
+            handle_change_message(session, &msg, tx, shared_redis).await?;
+        }
+        TalkProtocol::React { room_id, .. } => {
+            if !require_room(session, room_id, tx)?
+                || !require_unmuted(session, room_id, tx, shared_redis).await?
+            {
+                return Ok(());
+            }
+            handle_reaction(session, &msg, tx, shared_redis).await?;
+        }
+        TalkProtocol::Unreact { room_id, .. } => {
+            if !require_room(session, room_id, tx)? {
+                return Ok(());
+            }
+            handle_reaction(session, &msg, tx, shared_redis).await?;
+        }
+        TalkProtocol::MarkRead {
+            room_id,
+            uuid,
+            message_id,
+            ..
+        } => {
+            if !require_capability(session, capability::RECEIPTS, tx)?
+                || !require_room(session, room_id, tx)?
+            {
+                return Ok(());
+            }
+            let mut conn = shared_redis.lock().await;
+            // nobody can read past the newest message
+            let message_id = (*message_id).min(current_message_id(&mut conn, room_id)?);
+            if mark_read(&mut conn, room_id, uuid, message_id)? {
+                let response = TalkProtocol::MarkRead {
+                    room_id: room_id.to_string(),
+                    uuid: *uuid,
+                    username: session.username.clone(),
+                    message_id,
+                };
+                let _: () = conn.publish(room_channel(room_id), response.serialize()?)?;
+            }
+        }
+        // typing is only interesting right now, so it skips the history
+        TalkProtocol::Typing { room_id, .. } => {
+            if !require_capability(session, capability::TYPING, tx)?
+                || !require_room(session, room_id, tx)?
+            {
+                return Ok(());
+            }
+            let mut conn = shared_redis.lock().await;
+            let _: () = conn.publish(room_channel(room_id), msg.serialize()?)?;
+        }
+        TalkProtocol::SetTopic {
+            room_id,
+            uuid,
+            topic,
         } => {
-            let response = TalkProtocol::UsernameChanged {
+            if !require_room(session, room_id, tx)?
+                || !require_role(session, room_id, RoomRole::Moderator, tx, shared_redis).await?
+            {
+                return Ok(());
+            }
+            let topic = Some(topic.trim()).filter(|topic| !topic.is_empty());
+            {
+                let mut conn = shared_redis.lock().await;
+                set_topic(&mut conn, room_id, topic)?;
+            }
+
+            let response = TalkProtocol::TopicChanged {
                 uuid: *uuid,
-                username: username.clone(),
-                old_username: old_username.clone(),
-                unixtime: *unixtime,
+                username: session.username.clone(),
+                room_id: room_id.to_string(),
+                topic: topic.map(str::to_string),
+                unixtime: 0,
+                message_id: 0,
             };
+            publish_message(shared_redis, &response, room_id).await?;
+        }
+        TalkProtocol::SetRoomAccess {
+            room_id,
+            access,
+            password,
+        } => {
+            if !require_room(session, room_id, tx)? {
+                return Ok(());
+            }
+            // new clients join the default room first and could not get in anywhere else
+            if room_id == DEFAULT_ROOM {
+                return send_permission_denied("the default room is always public", tx);
+            }
+            if !require_role(session, room_id, RoomRole::Owner, tx, shared_redis).await? {
+                return Ok(());
+            }
+            let password = password.as_deref().filter(|password| !password.is_empty());
+            if *access == RoomAccess::Password && password.is_none() {
+                let response = TalkProtocol::Error {
+                    code: error_code::INVALID_FRAME.to_string(),
+                    message: "a password protected room needs a password".to_string(),
+                };
+                return send_to_client(tx, &response);
+            }
+            let password_hash = match password {
+                Some(password) => Some(hash_password_blocking(password).await?),
+                None => None,
+            };
+            {
+                let mut conn = shared_redis.lock().await;
+                set_access(&mut conn, room_id, *access, password_hash.as_deref())?;
+            }
 
+            let response = TalkProtocol::RoomAccessChanged {
+                uuid: session.uuid.unwrap_or_default(),
+                username: session.username.clone(),
+                room_id: room_id.to_string(),
+                access: *access,
+                unixtime: 0,
+                message_id: 0,
+            };
             publish_message(shared_redis, &response, room_id).await?;
         }
+        TalkProtocol::Invite { room_id, username } => {
+            if !require_room(session, room_id, tx)? {
+                return Ok(());
+            }
+            handle_invite(session, room_id, username, tx, shared_redis).await?;
+        }
+        TalkProtocol::Kick { room_id, .. }
+        | TalkProtocol::Ban { room_id, .. }
+        | TalkProtocol::Unban { room_id, .. }
+        | TalkProtocol::Mute { room_id, .. }
+        | TalkProtocol::SetRole { room_id, .. } => {
+            if !require_room(session, room_id, tx)? {
+                return Ok(());
+            }
+            handle_moderation(session, &msg, tx, shared_redis).await?;
+        }
+        TalkProtocol::Who { room_id } => {
+            if !require_capability(session, capability::PRESENCE, tx)?
+                || !require_reader(session, room_id, tx, shared_redis).await?
+            {
+                return Ok(());
+            }
+            let members = {
+                let mut conn = shared_redis.lock().await;
+                list_members(&mut conn, room_id)?
+            };
+            let response = TalkProtocol::Members {
+                room_id: room_id.to_string(),
+                members,
+            };
+            send_to_client(tx, &response)?;
+        }
+        TalkProtocol::ListRooms => {
+            if !require_capability(session, capability::DIRECTORY, tx)? {
+                return Ok(());
+            }
+            let rooms = {
+                let mut conn = shared_redis.lock().await;
+                list_rooms(&mut conn, get_unix_timestamp()?)?
+            };
+            send_to_client(tx, &TalkProtocol::RoomList { rooms })?;
+        }
 
         // Server -> Client events typically don't need handling here
         // These are usually sent from server to client, not received
@@ -123,25 +771,1007 @@ async fn handle_message(
     Ok(())
 }
 
-async fn handle_join(
-    room_id: &i32,
-    room_tx: &UnboundedSender<(i32, oneshot::Sender<()>)>,
+fn handle_hello(
+    session: &mut Session,
+    protocol_version: u16,
+    client_name: &str,
+    capabilities: &[String],
+    tx: &UnboundedSender<Message>,
+) -> Result<()> {
+    if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&protocol_version) {
+        return close_with_error(
+            session,
+            tx,
+            error_code::UNSUPPORTED_VERSION,
+            &format!(
+                "client speaks protocol {}, server supports {} to {}",
+                protocol_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+            ),
+        );
+    }
+
+    println!(
+        "[SERVER] Handshake with {} (protocol {})",
+        client_name, protocol_version
+    );
+    session.greeted = true;
+    session.capabilities = capabilities
+        .iter()
+        .filter(|c| capability::ALL.contains(&c.as_str()))
+        .cloned()
+        .collect();
+
+    let mut challenge = vec![0u8; CHALLENGE_LENGTH];
+    OsRng.fill_bytes(&mut challenge);
+    session.challenge = challenge.clone();
+
+    let response = TalkProtocol::Welcome {
+        server_version: env!("CARGO_PKG_VERSION").to_string(),
+        capabilities: session.capabilities.clone(),
+        motd: env::var("MOTD").ok(),
+        challenge,
+    };
+    send_to_client(tx, &response)
+}
+
+// Binds the uuid to the connection once the challenge was signed with the uuid's key
+async fn handle_authenticate(
+    session: &mut Session,
+    uuid: &Uuid,
+    public_key: &[u8],
+    signature: &[u8],
+    tx: &UnboundedSender<Message>,
+    shared_redis: &SharedRedis,
+) -> Result<()> {
+    let verified = verify_signature(&session.challenge, uuid, public_key, signature) && {
+        let mut conn = shared_redis.lock().await;
+        bind_public_key(&mut conn, uuid, public_key)?
+    };
+    if !verified {
+        return close_with_error(
+            session,
+            tx,
+            error_code::AUTH_FAILED,
+            "the signature does not match the key bound to this uuid",
+        );
+    }
+
+    session.uuid = Some(*uuid);
+    session.device_uuid = Some(*uuid);
+    send_to_client(tx, &TalkProtocol::Authenticated { uuid: *uuid })
+}
+
+// Switches the session to the account's identity and hands out a token for later connections
+async fn handle_login(
+    session: &mut Session,
+    account: Account,
+    token: Option<String>,
+    tx: &UnboundedSender<Message>,
+    subscribe_tx: &UnboundedSender<(Subscription, oneshot::Sender<()>)>,
+    shared_redis: &SharedRedis,
+) -> Result<()> {
+    let token = {
+        let mut conn = shared_redis.lock().await;
+        let token = match token {
+            Some(token) => token,
+            None => create_token(&mut conn, &account)?,
+        };
+
+        // keep the member lists in sync when logging in while already in rooms
+        if let Some(old_uuid) = session.uuid {
+            for room_id in &session.rooms {
+                leave_member(&mut conn, room_id, &old_uuid)?;
+                add_member(&mut conn, room_id, &account.uuid, &account.username)?;
+            }
+        }
+        token
+    };
+    // direct messages to the account reach every device logged in to it
+    handle_subscribe(Subscription::User(account.uuid), subscribe_tx).await?;
+
+    session.uuid = Some(account.uuid);
+    session.username = account.username.clone();
+    session.account = Some(account.username.clone());
+
+    let response = TalkProtocol::LoggedIn {
+        username: account.username,
+        uuid: account.uuid,
+        token,
+    };
+    send_to_client(tx, &response)
+}
+
+// Accounts own their name everywhere, guests are only found in the sender's rooms
+fn resolve_recipient(
+    conn: &mut Connection,
+    session: &Session,
+    username: &str,
+) -> Result<Option<Uuid>> {
+    if let Some(uuid) = owner_of(conn, username)? {
+        return Ok(Some(uuid));
+    }
+    for room_id in &session.rooms {
+        if let Some(member) = list_members(conn, room_id)?
+            .into_iter()
+            .find(|member| member.username.eq_ignore_ascii_case(username))
+        {
+            return Ok(Some(member.uuid));
+        }
+    }
+    Ok(None)
+}
+
+fn send_user_not_found(username: &str, tx: &UnboundedSender<Message>) -> Result<()> {
+    let response = TalkProtocol::Error {
+        code: error_code::USER_NOT_FOUND.to_string(),
+        message: format!("there is no user named '{}'", username),
+    };
+    send_to_client(tx, &response)
+}
+
+// Stored in the conversation's history and published to the inboxes of both users,
+// so the sender's other devices see it as well
+async fn handle_direct_message(
+    session: &Session,
+    mut msg: TalkProtocol,
+    tx: &UnboundedSender<Message>,
+    shared_redis: &SharedRedis,
+) -> Result<()> {
+    let (Some(sender), TalkProtocol::DirectMessage { recipient, .. }) = (session.uuid, &msg) else {
+        return Ok(());
+    };
+    let recipient_name = recipient.clone();
+    let mut conn = shared_redis.lock().await;
+    let Some(recipient) = resolve_recipient(&mut conn, session, &recipient_name)? else {
+        return send_user_not_found(&recipient_name, tx);
+    };
+
+    if let TalkProtocol::DirectMessage { recipient_uuid, .. } = &mut msg {
+        *recipient_uuid = recipient;
+    }
+    msg.stamp(
+        next_direct_id(&mut conn, &sender, &recipient)?,
+        get_unix_timestamp()?,
+    );
+    if let Err(e) = store_direct_message(&mut conn, &msg) {
+        eprintln!("[SERVER] Redis direct message error: {}", e);
+    }
+
+    let bin = msg.serialize()?;
+    let _: () = conn.publish(user_channel(&recipient), &bin)?;
+    if recipient != sender {
+        let _: () = conn.publish(user_channel(&sender), &bin)?;
+    }
+    Ok(())
+}
+
+async fn handle_fetch_conversation(
+    session: &Session,
+    username: &str,
+    limit: i64,
+    tx: &UnboundedSender<Message>,
+    shared_redis: &SharedRedis,
+) -> Result<()> {
+    let Some(sender) = session.uuid else {
+        return Ok(());
+    };
+    let messages = {
+        let mut conn = shared_redis.lock().await;
+        let Some(recipient) = resolve_recipient(&mut conn, session, username)? else {
+            return send_user_not_found(username, tx);
+        };
+        fetch_conversation(&mut conn, &sender, &recipient, limit)?
+    };
+    let response = TalkProtocol::Conversation {
+        username: username.to_string(),
+        messages,
+    };
+    send_to_client(tx, &response)
+}
+
+async fn username_allowed(
+    session: &Session,
+    username: &str,
+    shared_redis: &SharedRedis,
+) -> Result<bool> {
+    let owner = {
+        let mut conn = shared_redis.lock().await;
+        owner_of(&mut conn, username)?
+    };
+    Ok(owner.is_none() || owner == session.uuid)
+}
+
+fn send_reserved_error(username: &str, tx: &UnboundedSender<Message>) -> Result<()> {
+    let response = TalkProtocol::Error {
+        code: error_code::USERNAME_RESERVED.to_string(),
+        message: format!("'{}' belongs to a registered account", username),
+    };
+    send_to_client(tx, &response)
+}
+
+fn verify_signature(challenge: &[u8], uuid: &Uuid, public_key: &[u8], signature: &[u8]) -> bool {
+    let Ok(public_key) = <[u8; 32]>::try_from(public_key) else {
+        return false;
+    };
+    let Ok(key) = VerifyingKey::from_bytes(&public_key) else {
+        return false;
+    };
+    let Ok(signature) = Signature::from_slice(signature) else {
+        return false;
+    };
+    key.verify_strict(&auth_payload(challenge, uuid), &signature)
+        .is_ok()
+}
+
+// Answers with an error when the client uses a feature it did not negotiate
+fn require_capability(
+    session: &Session,
+    capability: &str,
+    tx: &UnboundedSender<Message>,
+) -> Result<bool> {
+    if session.supports(capability) {
+        return Ok(true);
+    }
+    let response = TalkProtocol::Error {
+        code: error_code::UNSUPPORTED_CAPABILITY.to_string(),
+        message: format!("'{}' was not negotiated", capability),
+    };
+    send_to_client(tx, &response)?;
+    Ok(false)
+}
+
+// Sends the error followed by a close frame and ignores everything afterwards
+// Every malformed frame is answered, the connection is closed after a few of them
+fn handle_invalid_frame(
+    session: &mut Session,
+    tx: &UnboundedSender<Message>,
+    error: FrameError,
+) -> Result<()> {
+    if session.closing {
+        return Ok(());
+    }
+    session.frame_violations += 1;
+    if session.frame_violations >= MAX_FRAME_VIOLATIONS {
+        return close_with(
+            session,
+            tx,
+            error.close_code(),
+            error.code(),
+            &error.message(),
+        );
+    }
+    let response = TalkProtocol::Error {
+        code: error.code().to_string(),
+        message: error.message(),
+    };
+    send_to_client(tx, &response)
+}
+
+// Creators, members and invited users always get in,
+// everyone else needs what the room's access mode asks for
+async fn check_room_access(
+    room_id: &str,
+    uuid: &Uuid,
+    password: Option<&str>,
+    shared_redis: &SharedRedis,
+) -> Result<Option<TalkProtocol>> {
+    let (room, admitted, password_hash) = {
+        let mut conn = shared_redis.lock().await;
+        // a room that does not exist yet is created by this join
+        let Some(room) = room_metadata(&mut conn, room_id)? else {
+            return Ok(None);
+        };
+        let admitted = room.access == RoomAccess::Public
+            || creator_uuid(&mut conn, room_id)? == Some(*uuid)
+            || is_member(&mut conn, room_id, uuid)?
+            || is_invited(&mut conn, room_id, uuid)?;
+        (room, admitted, password_hash(&mut conn, room_id)?)
+    };
+    // the password is checked once the lock is released, argon2 takes a while
+    let admitted = admitted
+        || match (room.access, password, password_hash) {
+            (RoomAccess::Password, Some(password), Some(password_hash)) => {
+                verify_password_blocking(password, &password_hash).await?
+            }
+            _ => false,
+        };
+    if admitted {
+        return Ok(None);
+    }
+
+    let (code, message) = match room.access {
+        RoomAccess::Password => (
+            error_code::ROOM_PASSWORD_REQUIRED,
+            format!(
+                "{} needs a password, join with /room {} {{password}}",
+                room.display_name, room.display_name
+            ),
+        ),
+        _ => (
+            error_code::ROOM_INVITE_ONLY,
+            format!(
+                "{} is invite only, ask a member for an invite",
+                room.display_name
+            ),
+        ),
+    };
+    Ok(Some(TalkProtocol::Error {
+        code: code.to_string(),
+        message,
+    }))
+}
+
+fn send_permission_denied(message: &str, tx: &UnboundedSender<Message>) -> Result<()> {
+    let response = TalkProtocol::Error {
+        code: error_code::PERMISSION_DENIED.to_string(),
+        message: message.to_string(),
+    };
+    send_to_client(tx, &response)
+}
+
+// Only the owner may change who can join a room, moderators may remove its members and set its topic
+async fn require_role(
+    session: &Session,
+    room_id: &str,
+    required: RoomRole,
+    tx: &UnboundedSender<Message>,
+    shared_redis: &SharedRedis,
+) -> Result<bool> {
+    let Some(uuid) = session.uuid else {
+        return Ok(false);
+    };
+    let sender_role = {
+        let mut conn = shared_redis.lock().await;
+        role(&mut conn, room_id, &uuid)?
+    };
+    if sender_role >= required {
+        return Ok(true);
+    }
+    let message = match required {
+        RoomRole::Owner => "only the owner of the room can do that",
+        _ => "only moderators of the room can do that",
+    };
+    send_permission_denied(message, tx)?;
+    Ok(false)
+}
+
+async fn require_unmuted(
+    session: &Session,
+    room_id: &str,
+    tx: &UnboundedSender<Message>,
+    shared_redis: &SharedRedis,
+) -> Result<bool> {
+    let Some(uuid) = session.uuid else {
+        return Ok(false);
+    };
+    let now = get_unix_timestamp()?;
+    let until = {
+        let mut conn = shared_redis.lock().await;
+        muted_until(&mut conn, room_id, &uuid, now)?
+    };
+    let Some(until) = until else {
+        return Ok(true);
+    };
+    let minutes = until.saturating_sub(now).div_ceil(60_000);
+    let response = TalkProtocol::Error {
+        code: error_code::MUTED.to_string(),
+        message: format!("you are muted in this room for {} more minute(s)", minutes),
+    };
+    send_to_client(tx, &response)?;
+    Ok(false)
+}
+
+// Moderators act on members below their own role, only the owner hands out roles.
+// The outcome is published to the room as a Moderated event
+async fn handle_moderation(
+    session: &Session,
+    msg: &TalkProtocol,
+    tx: &UnboundedSender<Message>,
+    shared_redis: &SharedRedis,
+) -> Result<()> {
+    let now = get_unix_timestamp()?;
+    let (room_id, target, action, reason, required) = match msg {
+        TalkProtocol::Kick {
+            room_id,
+            username,
+            reason,
+        } => (
+            room_id,
+            username,
+            ModerationAction::Kicked,
+            reason.clone(),
+            RoomRole::Moderator,
+        ),
+        TalkProtocol::Ban {
+            room_id,
+            username,
+            reason,
+        } => (
+            room_id,
+            username,
+            ModerationAction::Banned,
+            reason.clone(),
+            RoomRole::Moderator,
+        ),
+        TalkProtocol::Unban { room_id, username } => (
+            room_id,
+            username,
+            ModerationAction::Unbanned,
+            None,
+            RoomRole::Moderator,
+        ),
+        TalkProtocol::Mute {
+            room_id,
+            username,
+            duration,
+        } => {
+            if *duration > MAX_MUTE_SECONDS {
+                let response = TalkProtocol::Error {
+                    code: error_code::MUTE_TOO_LONG.to_string(),
+                    message: format!(
+                        "a mute lasts at most {} days",
+                        MAX_MUTE_SECONDS / (24 * 60 * 60)
+                    ),
+                };
+                return send_to_client(tx, &response);
+            }
+            let until = now.saturating_add(duration.saturating_mul(1000));
+            (
+                room_id,
+                username,
+                ModerationAction::Muted { until },
+                None,
+                RoomRole::Moderator,
+            )
+        }
+        TalkProtocol::SetRole {
+            room_id,
+            username,
+            role,
+        } => {
+            if *role == RoomRole::Owner {
+                return send_permission_denied("a room only has one owner, its creator", tx);
+            }
+            let action = ModerationAction::RoleChanged { role: *role };
+            (room_id, username, action, None, RoomRole::Owner)
+        }
+        _ => return Ok(()),
+    };
+    let Some(uuid) = session.uuid else {
+        return Ok(());
+    };
+    if !require_role(session, room_id, required, tx, shared_redis).await? {
+        return Ok(());
+    }
+
+    let target_uuid = {
+        let mut conn = shared_redis.lock().await;
+        let target_uuid = match action {
+            ModerationAction::Unbanned => banned_uuid(&mut conn, room_id, target)?,
+            _ => resolve_recipient(&mut conn, session, target)?,
+        };
+        let Some(target_uuid) = target_uuid else {
+            return send_user_not_found(target, tx);
+        };
+        if role(&mut conn, room_id, &target_uuid)? >= role(&mut conn, room_id, &uuid)? {
+            return send_permission_denied(&format!("you can't moderate {}", target), tx);
+        }
+        match action {
+            ModerationAction::Kicked => remove_member(&mut conn, room_id, &target_uuid)?,
+            ModerationAction::Banned => {
+                ban(&mut conn, room_id, &target_uuid, target)?;
+                remove_member(&mut conn, room_id, &target_uuid)?;
+            }
+            ModerationAction::Unbanned => {
+                unban(&mut conn, room_id, &target_uuid)?;
+            }
+            ModerationAction::Muted { until } => mute(&mut conn, room_id, &target_uuid, until)?,
+            ModerationAction::RoleChanged { role } => {
+                set_role(&mut conn, room_id, &target_uuid, role)?
+            }
+        }
+        target_uuid
+    };
+
+    let response = TalkProtocol::Moderated {
+        uuid,
+        username: session.username.clone(),
+        room_id: room_id.to_string(),
+        target_uuid,
+        target: target.to_string(),
+        action,
+        reason,
+        unixtime: 0,
+        message_id: 0,
+    };
+    publish_message(shared_redis, &response, room_id).await
+}
+
+// Only authors edit their messages, moderators may also delete anyone's. The stored message
+// is rewritten and the change relayed to the room, it gets no id of its own
+async fn handle_change_message(
+    session: &Session,
+    msg: &TalkProtocol,
+    tx: &UnboundedSender<Message>,
+    shared_redis: &SharedRedis,
+) -> Result<()> {
+    let (room_id, message_id) = match msg {
+        TalkProtocol::EditMessage {
+            room_id,
+            message_id,
+            ..
+        }
+        | TalkProtocol::DeleteMessage {
+            room_id,
+            message_id,
+            ..
+        } => (room_id, *message_id),
+        _ => return Ok(()),
+    };
+    let Some(uuid) = session.uuid else {
+        return Ok(());
+    };
+
+    let mut conn = shared_redis.lock().await;
+    let Some(stored) = stored_message(&mut conn, room_id, message_id)? else {
+        return send_message_not_found(tx);
+    };
+    if stored.uuid != uuid {
+        // an edit by someone else would still carry the author's name
+        if matches!(msg, TalkProtocol::EditMessage { .. }) {
+            return send_permission_denied("you can only edit your own messages", tx);
+        }
+        if role(&mut conn, room_id, &uuid)? < RoomRole::Moderator {
+            return send_permission_denied("you can only delete your own messages", tx);
+        }
+    }
+
+    let mut changed = stored;
+    match msg {
+        TalkProtocol::EditMessage { text, .. } => {
+            changed.text = text.clone();
+            changed.mentions = resolve_mentions(text, &list_members(&mut conn, room_id)?);
+            changed.edited = true;
+        }
+        _ => {
+            changed.text.clear();
+            changed.reactions.clear();
+            changed.deleted = true;
+        }
+    }
+    replace_event(
+        &mut conn,
+        &TalkProtocol::PostMessage { message: changed },
+        room_id,
+    )?;
+    let _: () = conn.publish(room_channel(room_id), msg.serialize()?)?;
+    Ok(())
+}
+
+// Reactions are counted on the stored message, so a replayed history shows them as well
+async fn handle_reaction(
+    session: &Session,
+    msg: &TalkProtocol,
+    tx: &UnboundedSender<Message>,
+    shared_redis: &SharedRedis,
+) -> Result<()> {
+    let (room_id, message_id, emoji, add) = match msg {
+        TalkProtocol::React {
+            room_id,
+            message_id,
+            emoji,
+            ..
+        } => (room_id, *message_id, emoji, true),
+        TalkProtocol::Unreact {
+            room_id,
+            message_id,
+            emoji,
+            ..
+        } => (room_id, *message_id, emoji, false),
+        _ => return Ok(()),
+    };
+    let Some(uuid) = session.uuid else {
+        return Ok(());
+    };
+
+    let mut conn = shared_redis.lock().await;
+    let Some(mut message) = stored_message(&mut conn, room_id, message_id)? else {
+        return send_message_not_found(tx);
+    };
+    if add
+        && message.reactions.len() >= MAX_REACTIONS
+        && !message
+            .reactions
+            .iter()
+            .any(|reaction| reaction.emoji == *emoji)
+    {
+        let response = TalkProtocol::Error {
+            code: error_code::TOO_MANY_REACTIONS.to_string(),
+            message: format!(
+                "a message can't have more than {} different reactions",
+                MAX_REACTIONS
+            ),
+        };
+        return send_to_client(tx, &response);
+    }
+    let changed = if add {
+        message.react(emoji, uuid)
+    } else {
+        message.unreact(emoji, uuid)
+    };
+    // reacting twice or taking back a missing reaction is not relayed
+    if !changed {
+        return Ok(());
+    }
+    replace_event(&mut conn, &TalkProtocol::PostMessage { message }, room_id)?;
+    let _: () = conn.publish(room_channel(room_id), msg.serialize()?)?;
+    Ok(())
+}
+
+// Only messages that were not deleted can be changed or reacted to
+fn stored_message(
+    conn: &mut Connection,
+    room_id: &str,
+    message_id: u64,
+) -> Result<Option<TalkMessage>> {
+    Ok(match fetch_event(conn, room_id, message_id)? {
+        Some(TalkProtocol::PostMessage { message }) if !message.deleted => Some(message),
+        _ => None,
+    })
+}
+
+fn send_message_not_found(tx: &UnboundedSender<Message>) -> Result<()> {
+    let response = TalkProtocol::Error {
+        code: error_code::MESSAGE_NOT_FOUND.to_string(),
+        message: "the message does not exist or was deleted".to_string(),
+    };
+    send_to_client(tx, &response)
+}
+
+// The removed connection stops listening to the room, the Moderated event already told its client
+async fn handle_removed(
+    session: &mut Session,
+    room_id: &str,
+    target: &Uuid,
+    subscribe_tx: &UnboundedSender<(Subscription, oneshot::Sender<()>)>,
+    shared_redis: &SharedRedis,
+) -> Result<()> {
+    if session.uuid != Some(*target) || !session.rooms.iter().any(|joined| joined == room_id) {
+        return Ok(());
+    }
+    handle_presence_leave(session, room_id, target, shared_redis).await?;
+    handle_subscribe(Subscription::LeaveRoom(room_id.to_string()), subscribe_tx).await
+}
+
+// The invitee learns about it through their inbox, the inviter gets the same notice
+async fn handle_invite(
+    session: &Session,
+    room_id: &str,
+    username: &str,
+    tx: &UnboundedSender<Message>,
+    shared_redis: &SharedRedis,
+) -> Result<()> {
+    let mut conn = shared_redis.lock().await;
+    let Some(invitee) = resolve_recipient(&mut conn, session, username)? else {
+        return send_user_not_found(username, tx);
+    };
+    invite(&mut conn, room_id, &invitee)?;
+
+    let display_name = room_metadata(&mut conn, room_id)?
+        .map(|room| room.display_name)
+        .unwrap_or_else(|| room_id.to_string());
+    let response = TalkProtocol::Invited {
+        room_id: room_id.to_string(),
+        display_name,
+        username: session.username.clone(),
+        invitee: username.to_string(),
+    };
+    let _: () = conn.publish(user_channel(&invitee), response.serialize()?)?;
+    if Some(invitee) != session.uuid {
+        send_to_client(tx, &response)?;
+    }
+    Ok(())
+}
+
+// Rooms can only be used by connections that joined them
+fn require_room(session: &Session, room_id: &str, tx: &UnboundedSender<Message>) -> Result<bool> {
+    if session.rooms.iter().any(|joined| joined == room_id) {
+        return Ok(true);
+    }
+    let response = TalkProtocol::Error {
+        code: error_code::NOT_IN_ROOM.to_string(),
+        message: format!("join room {} before using it", room_id),
+    };
+    send_to_client(tx, &response)?;
+    Ok(false)
+}
+
+// History and members are only shown to connections in the room, a ban ends that right away
+// even before the removal reached this connection
+async fn require_reader(
+    session: &Session,
+    room_id: &str,
+    tx: &UnboundedSender<Message>,
+    shared_redis: &SharedRedis,
+) -> Result<bool> {
+    let Some(uuid) = session.uuid else {
+        return Ok(false);
+    };
+    if !require_room(session, room_id, tx)? {
+        return Ok(false);
+    }
+    let banned = {
+        let mut conn = shared_redis.lock().await;
+        is_banned(&mut conn, room_id, &uuid)?
+    };
+    if banned {
+        send_permission_denied("you are banned from this room", tx)?;
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+fn close_with_error(
+    session: &mut Session,
+    tx: &UnboundedSender<Message>,
+    code: &str,
+    message: &str,
+) -> Result<()> {
+    close_with(session, tx, CloseCode::Policy, code, message)
+}
+
+fn close_with(
+    session: &mut Session,
+    tx: &UnboundedSender<Message>,
+    close_code: CloseCode,
+    code: &str,
+    message: &str,
+) -> Result<()> {
+    session.closing = true;
+    let response = TalkProtocol::Error {
+        code: code.to_string(),
+        message: message.to_string(),
+    };
+    send_to_client(tx, &response)?;
+    tx.send(Message::Close(Some(CloseFrame {
+        code: close_code,
+        reason: code.to_string().into(),
+    })))?;
+    Ok(())
+}
+
+fn validate_frame(msg: &TalkProtocol) -> Result<(), ValidationError> {
+    match msg {
+        TalkProtocol::PostMessage { message } => validate_message(&message.text),
+        TalkProtocol::DirectMessage {
+            recipient, text, ..
+        } => validate_username(recipient).and_then(|_| validate_message(text)),
+        TalkProtocol::JoinRoom {
+            room_id, username, ..
+        } => validate_room_name(room_id).and_then(|_| validate_username(username)),
+        TalkProtocol::SetTopic { room_id, topic, .. } => {
+            validate_room_name(room_id).and_then(|_| validate_topic(topic))
+        }
+        TalkProtocol::SetRoomAccess { room_id, .. }
+        | TalkProtocol::DeleteMessage { room_id, .. }
+        | TalkProtocol::Typing { room_id, .. }
+        | TalkProtocol::MarkRead { room_id, .. } => validate_room_name(room_id),
+        TalkProtocol::EditMessage { room_id, text, .. } => {
+            validate_room_name(room_id).and_then(|_| validate_message(text))
+        }
+        TalkProtocol::React { room_id, emoji, .. }
+        | TalkProtocol::Unreact { room_id, emoji, .. } => {
+            validate_room_name(room_id).and_then(|_| validate_reaction(emoji))
+        }
+        TalkProtocol::Kick {
+            room_id,
+            username,
+            reason,
+        }
+        | TalkProtocol::Ban {
+            room_id,
+            username,
+            reason,
+        } => validate_room_name(room_id)
+            .and_then(|_| validate_username(username))
+            .and_then(|_| reason.as_deref().map_or(Ok(()), validate_message)),
+        TalkProtocol::Unban { room_id, username }
+        | TalkProtocol::Mute {
+            room_id, username, ..
+        }
+        | TalkProtocol::SetRole {
+            room_id, username, ..
+        } => validate_room_name(room_id).and_then(|_| validate_username(username)),
+        TalkProtocol::Invite { room_id, username } => {
+            validate_room_name(room_id).and_then(|_| validate_username(username))
+        }
+        TalkProtocol::ChangeName { username, .. } => validate_username(username),
+        TalkProtocol::Register { username, password } => {
+            validate_username(username).and_then(|_| validate_password(password))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn guest_name(uuid: &Uuid) -> String {
+    format!("guest-{}", &uuid.simple().to_string()[..4])
+}
+
+// A frame has to pass the connection's bucket and the one shared by all connections of the uuid,
+// clients that keep hitting the limit are disconnected
+async fn check_rate_limit(
+    session: &mut Session,
+    tx: &UnboundedSender<Message>,
+    shared_redis: &SharedRedis,
+) -> Result<bool> {
+    let mut allowed = session.limiter.try_take();
+    if allowed && let Some(uuid) = session.uuid {
+        let mut conn = shared_redis.lock().await;
+        allowed = take_token(&mut conn, &uuid, &session.limiter.config)?;
+    }
+    if allowed {
+        return Ok(true);
+    }
+
+    if session.limiter.strike() {
+        close_with_error(
+            session,
+            tx,
+            error_code::RATE_LIMITED,
+            "too many frames, disconnecting",
+        )?;
+    } else {
+        let response = TalkProtocol::Error {
+            code: error_code::RATE_LIMITED.to_string(),
+            message: "slow down, the frame was dropped".to_string(),
+        };
+        send_to_client(tx, &response)?;
+    }
+    Ok(false)
+}
+
+async fn handle_subscribe(
+    subscription: Subscription,
+    subscribe_tx: &UnboundedSender<(Subscription, oneshot::Sender<()>)>,
 ) -> Result<()> {
     let (ack_tx, ack_rx) = oneshot::channel();
-    room_tx.send((*room_id, ack_tx))?;
+    subscribe_tx.send((subscription, ack_tx))?;
     ack_rx.await?;
     Ok(())
 }
 
+async fn handle_presence_join(
+    session: &mut Session,
+    room_id: &str,
+    uuid: &Uuid,
+    username: &str,
+    shared_redis: &SharedRedis,
+) -> Result<()> {
+    let mut conn = shared_redis.lock().await;
+    // joining a room twice on the same connection only updates the name
+    if session.rooms.iter().any(|joined| joined == room_id) {
+        rename_member(&mut conn, room_id, uuid, username)?;
+    } else {
+        add_member(&mut conn, room_id, uuid, username)?;
+        session.rooms.push(room_id.to_string());
+    }
+    session.username = username.to_string();
+    Ok(())
+}
+
+async fn handle_presence_refresh(session: &Session, shared_redis: &SharedRedis) -> Result<()> {
+    let Some(uuid) = session.uuid else {
+        return Ok(());
+    };
+    let mut conn = shared_redis.lock().await;
+    for room_id in &session.rooms {
+        refresh_member(&mut conn, room_id, &uuid)?;
+    }
+    Ok(())
+}
+
+// True once the user has no connection left in the room
+async fn handle_presence_leave(
+    session: &mut Session,
+    room_id: &str,
+    uuid: &Uuid,
+    shared_redis: &SharedRedis,
+) -> Result<bool> {
+    let mut conn = shared_redis.lock().await;
+    let gone = leave_member(&mut conn, room_id, uuid)?;
+
+    session.rooms.retain(|joined| joined != room_id);
+    Ok(gone)
+}
+
+async fn handle_fetch(
+    room_id: &str,
+    limit: i64,
+    fetch_before: u64,
+    tx: &UnboundedSender<Message>,
+    shared_redis: &SharedRedis,
+) -> Result<()> {
+    let events = {
+        let mut conn = shared_redis.lock().await;
+        fetch_history(&mut conn, room_id, limit, fetch_before)?
+    };
+    let response = TalkProtocol::History {
+        room_id: room_id.to_string(),
+        text: events,
+    };
+    send_to_client(tx, &response)
+}
+
+// Where the joining user stopped reading and how far the members of the room got
+async fn handle_read_state(
+    room_id: &str,
+    uuid: &Uuid,
+    tx: &UnboundedSender<Message>,
+    shared_redis: &SharedRedis,
+) -> Result<()> {
+    let response = {
+        let mut conn = shared_redis.lock().await;
+        let positions = read_positions(&mut conn, room_id)?;
+        let receipts = list_members(&mut conn, room_id)?
+            .into_iter()
+            .filter(|member| member.uuid != *uuid)
+            .filter_map(|member| {
+                Some(ReadReceipt {
+                    message_id: *positions.get(&member.uuid)?,
+                    uuid: member.uuid,
+                    username: member.username,
+                })
+            })
+            .collect();
+        TalkProtocol::ReadState {
+            room_id: room_id.to_string(),
+            last_read: last_read(&mut conn, room_id, uuid)?,
+            receipts,
+        }
+    };
+    send_to_client(tx, &response)
+}
+
+async fn handle_replay(
+    room_id: &str,
+    tx: &UnboundedSender<Message>,
+    shared_redis: &SharedRedis,
+) -> Result<()> {
+    let events = {
+        let mut conn = shared_redis.lock().await;
+        fetch_latest(&mut conn, room_id, replay_limit())?
+    };
+    let response = TalkProtocol::History {
+        room_id: room_id.to_string(),
+        text: events,
+    };
+    send_to_client(tx, &response)
+}
+
+fn send_to_client(tx: &UnboundedSender<Message>, msg: &TalkProtocol) -> Result<()> {
+    tx.send(Message::Binary(msg.serialize()?.into()))?;
+    Ok(())
+}
+
+// Stamps the event with the room's next id and the server time before relaying it
 async fn publish_message(
     shared_redis: &SharedRedis,
     msg: &TalkProtocol,
-    room_id: &i32,
+    room_id: &str,
 ) -> Result<()> {
     let mut conn = shared_redis.lock().await;
-    let msg_json = msg.serialize()?;
+    let mut event = msg.clone();
+    let now = get_unix_timestamp()?;
+    event.stamp(next_message_id(&mut conn, room_id)?, now);
+    let msg_json = event.serialize()?;
     println!("[SERVER] Publishing message: {:?}", msg_json);
-    match conn.publish(room_id, msg_json) {
+    if let Err(e) = store_event(&mut conn, &event, room_id) {
+        eprintln!("[SERVER] Redis history error: {}", e);
+    }
+    if let Err(e) = touch_room(&mut conn, room_id, now) {
+        eprintln!("[SERVER] Redis room registry error: {}", e);
+    }
+    match conn.publish(room_channel(room_id), msg_json) {
         Ok(()) => {}
         Err(e) => {
             eprintln!("[SERVER] Redis publish error: {}", e);